@@ -1,5 +1,4 @@
 use std::collections::HashMap;
-use std::fmt::Display;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -7,13 +6,127 @@ use anyhow::{Context, Result};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::dates::{self, DateSource};
 use crate::interlude::*;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
+    // Note: plain values must come before tables, otherwise serializing to TOML fails.
+    /// Extensions (case-insensitive) of files recognized as images when scanning. Files in formats
+    /// which can't be decoded are still tracked, but have no thumbnail.
+    #[serde(default = "default_image_extensions")]
+    pub image_extensions: Vec<String>,
+    /// Extensions (case-insensitive) of files recognized as videos when scanning.
+    #[serde(default = "default_video_extensions")]
+    pub video_extensions: Vec<String>,
+    /// Sources of file dates, from most to least trusted. The date of a file is taken from the
+    /// first source having a candidate at any of the file's locations. Unlisted sources are
+    /// ignored, except for filesystem timestamps used as a fallback (see `fs_date_fallback`).
+    #[serde(default = "dates::default_priority")]
+    pub date_priority: Vec<DateSource>,
+    /// Files with dates from different sources (or locations) differing by more than this many
+    /// hours are tagged with `dates::CONFLICT_TAG` for review.
+    #[serde(default = "default_date_conflict_hours")]
+    pub date_conflict_hours: u32,
+    /// Whether creation and modification times of files are recorded, and used as low-confidence
+    /// dates of files having no date from other sources. Changing it affects only files scanned
+    /// afterwards - use `scan --deep` to apply it to files already scanned.
+    #[serde(default)]
+    pub fs_date_fallback: bool,
+    /// Dump of places in GeoNames format (see `geocode::Places`), used to name places where photos
+    /// were taken. Without it, only GPS positions of files are recorded. A relative path is
+    /// relative to the config file.
+    #[serde(default)]
+    pub places_file: Option<PathBuf>,
+    /// Images with both dimensions smaller than this are skipped when scanning, as they're
+    /// probably thumbnails or icons.
+    #[serde(default)]
+    pub ignore_small: Option<MinSize>,
+    /// Overrides of `ignore_small` for specific markers. Use `{ w = 0, h = 0 }` to track all
+    /// images on a marker.
+    #[serde(default)]
+    pub ignore_small_per_marker: HashMap<String, MinSize>,
+    /// Overrides of `fs_date_fallback` for specific markers - e.g. file times in trees restored
+    /// from a backup are usually the time of restoring.
+    #[serde(default)]
+    pub fs_date_fallback_per_marker: HashMap<String, bool>,
     pub markers: Markers,
     pub date_path: DatePathsPerMarker,
+    /// Templates of paths at which files are put when replicated onto a marker (see
+    /// `replicate::expand_template`). Markers not listed here use the source path.
+    #[serde(default)]
+    pub replicate_path: HashMap<String, String>,
+}
+
+pub fn default_image_extensions() -> Vec<String> {
+    [
+        "jpg", "jpeg", "png", "gif", "webp", "tif", "tiff", "heic", "heif",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+pub fn default_video_extensions() -> Vec<String> {
+    ["mp4", "mov", "m4v", "3gp"].map(String::from).to_vec()
+}
+
+pub fn default_date_conflict_hours() -> u32 {
+    24
+}
+
+impl Config {
+    /// Threshold below which images are ignored on `marker`, if any.
+    pub fn ignore_small_at(&self, marker: &str) -> Option<MinSize> {
+        self.ignore_small_per_marker
+            .get(marker)
+            .or(self.ignore_small.as_ref())
+            .copied()
+            .filter(|min| min.w > 0 || min.h > 0)
+    }
+
+    /// Whether filesystem timestamps of files on `marker` can be used as their dates.
+    pub fn fs_date_fallback_at(&self, marker: &str) -> bool {
+        self.fs_date_fallback_per_marker
+            .get(marker)
+            .copied()
+            .unwrap_or(self.fs_date_fallback)
+    }
+
+    /// Filesystem sources listed in `date_priority`, which are never used because
+    /// `fs_date_fallback` is off for all markers, so filesystem timestamps aren't recorded.
+    pub fn unused_date_sources(&self) -> Vec<DateSource> {
+        let recorded =
+            self.fs_date_fallback || self.fs_date_fallback_per_marker.values().any(|&on| on);
+        match recorded {
+            true => Vec::new(),
+            false => self
+                .date_priority
+                .iter()
+                .copied()
+                .filter(|source| source.is_filesystem())
+                .collect(),
+        }
+    }
+
+    /// Max difference between candidate dates of a file not considered a conflict.
+    pub fn max_date_spread(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.date_conflict_hours.into())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MinSize {
+    pub w: u32,
+    pub h: u32,
+}
+
+impl MinSize {
+    /// Whether an image of given dimensions is big enough, i.e. reaches the minimum in at least
+    /// one dimension.
+    pub fn fits(&self, w: u32, h: u32) -> bool {
+        w >= self.w || h >= self.h
+    }
 }
 
 pub type DatePathsPerMarker = HashMap<String, Vec<DatePath>>;
@@ -30,8 +143,13 @@ pub struct DatePath {
     pub path: Regex,
 }
 
-pub fn read<P: AsRef<Path> + Display>(path: P) -> Result<Config> {
-    let raw = fs::read_to_string(&path).context("reading config file")?;
-    let config = toml::from_str(&raw).with_context(|| ifmt!("reading config file '{path}'"))?;
+pub fn read(path: impl AsRef<Path>) -> Result<Config> {
+    let path = path.as_ref();
+    let raw = fs::read_to_string(path).with_context(|| ifmt!("reading config file " path;?))?;
+    let mut config: Config =
+        toml::from_str(&raw).with_context(|| ifmt!("parsing config file " path;?))?;
+    if let (Some(places_file), Some(dir)) = (&mut config.places_file, path.parent()) {
+        *places_file = dir.join(&places_file);
+    }
     Ok(config)
 }
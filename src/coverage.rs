@@ -0,0 +1,236 @@
+//! Reports on how well files are backed up, i.e. on how many distinct marker trees each file is
+//! present.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+/// Files present on fewer than `min_markers` distinct marker trees, grouped by marker and
+/// directory. Note: files with no locations at all are not included.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub min_markers: u32,
+    /// Number of distinct files (by hash) in the report.
+    pub files: u64,
+    /// Total size of distinct files in the report.
+    pub bytes: u64,
+    /// Number of distinct files in the report with unknown size (scanned by old backer versions).
+    pub unknown_size: u64,
+    pub groups: Vec<Group>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Group {
+    pub marker: String,
+    pub dir: String,
+    /// Total size of distinct files in the group.
+    pub bytes: u64,
+    pub files: Vec<Entry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub path: String,
+    pub hash: String,
+    /// Number of distinct markers the file is present on.
+    pub markers: u32,
+    pub size: Option<u64>,
+}
+
+pub fn report(db: &Connection, min_markers: u32) -> Result<Report> {
+    let mut query = db.prepare(
+        "WITH counts AS (
+            SELECT file_id, COUNT(DISTINCT backend_tag) AS markers
+            FROM location
+            GROUP BY file_id
+        )
+        SELECT backend_tag, path, hash, file.size, markers
+            FROM location
+            JOIN counts USING (file_id)
+            JOIN file ON file.rowid = file_id
+            WHERE markers < ?
+            ORDER BY backend_tag, path",
+    )?;
+    let rows = query.query_map(params![min_markers], |row| {
+        let marker: String = row.get(0)?;
+        let entry = Entry {
+            path: row.get(1)?,
+            hash: row.get(2)?,
+            size: row.get(3)?,
+            markers: row.get(4)?,
+        };
+        Ok((marker, entry))
+    })?;
+
+    let mut groups = BTreeMap::<(String, String), Group>::new();
+    let mut seen = HashSet::new();
+    // Copies of a file in the same group count once towards its size, too.
+    let mut seen_in_group = HashSet::new();
+    let (mut files, mut bytes, mut unknown_size) = (0, 0, 0);
+    for row in rows {
+        let (marker, entry) = row?;
+        if seen.insert(entry.hash.clone()) {
+            files += 1;
+            match entry.size {
+                Some(size) => bytes += size,
+                None => unknown_size += 1,
+            }
+        }
+        let dir = match entry.path.rsplit_once('/') {
+            Some((dir, _)) => dir.to_string(),
+            None => String::new(),
+        };
+        let key = (marker.clone(), dir.clone());
+        let first_in_group = seen_in_group.insert((key.clone(), entry.hash.clone()));
+        let group = groups.entry(key).or_insert_with(|| Group {
+            marker,
+            dir,
+            bytes: 0,
+            files: Vec::new(),
+        });
+        if first_in_group {
+            group.bytes += entry.size.unwrap_or(0);
+        }
+        group.files.push(entry);
+    }
+
+    Ok(Report {
+        min_markers,
+        files,
+        bytes,
+        unknown_size,
+        groups: groups.into_values().collect(),
+    })
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for group in &self.groups {
+            writeln!(
+                f,
+                "{}: {}/ - {} file(s), {}",
+                group.marker,
+                group.dir,
+                group.files.len(),
+                human_bytes(group.bytes)
+            )?;
+            for entry in &group.files {
+                writeln!(f, "  {} [markers: {}]", entry.path, entry.markers)?;
+            }
+        }
+        write!(
+            f,
+            "TOTAL: {} file(s) on fewer than {} marker(s), {}",
+            self.files,
+            self.min_markers,
+            human_bytes(self.bytes)
+        )?;
+        if self.unknown_size > 0 {
+            write!(f, " (+{} file(s) of unknown size)", self.unknown_size)?;
+        }
+        writeln!(f)
+    }
+}
+
+/// Format `n` bytes with a binary unit suffix, e.g. "1.5 MiB".
+pub fn human_bytes(n: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut value = n as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::db;
+    use crate::model::FileInfo;
+
+    use super::*;
+
+    #[test]
+    fn report_files_on_single_marker() {
+        // arrange
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let add = |marker: &str, path: &str, hash: &str, size: u64| {
+            let info = FileInfo {
+                hash: hash.to_string(),
+                size: Some(size),
+                ..Default::default()
+            };
+            db::upsert(&conn, marker, path, &info).unwrap();
+        };
+        add("marker-a", "dir/backed-up.jpg", "hash-1", 100);
+        add("marker-b", "other/backed-up.jpg", "hash-1", 100);
+        add("marker-a", "dir/single.jpg", "hash-2", 2000);
+        add("marker-a", "dir/sub/copy-of-single.jpg", "hash-2", 2000);
+        add("marker-b", "only-b.jpg", "hash-3", 30);
+
+        // act
+
+        let report = report(&conn, 2).unwrap();
+
+        // assert
+
+        assert_eq!(report.files, 2);
+        assert_eq!(report.bytes, 2030);
+        let groups: Vec<_> = report
+            .groups
+            .iter()
+            .map(|g| (g.marker.as_str(), g.dir.as_str(), g.files.len(), g.bytes))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                ("marker-a", "dir", 1, 2000),
+                ("marker-a", "dir/sub", 1, 2000),
+                ("marker-b", "", 1, 30),
+            ]
+        );
+    }
+
+    #[test]
+    fn count_copies_in_one_directory_once() {
+        // arrange
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let info = FileInfo {
+            hash: "hash-1".to_string(),
+            size: Some(100),
+            ..Default::default()
+        };
+        db::upsert(&conn, "marker-a", "dir/photo.jpg", &info).unwrap();
+        db::upsert(&conn, "marker-a", "dir/photo (copy).jpg", &info).unwrap();
+
+        // act
+
+        let report = report(&conn, 2).unwrap();
+
+        // assert
+
+        assert_eq!((report.files, report.bytes), (1, 100));
+        assert_eq!(report.groups.len(), 1);
+        assert_eq!(report.groups[0].files.len(), 2);
+        assert_eq!(report.groups[0].bytes, 100);
+    }
+
+    #[test]
+    fn human_bytes_units() {
+        assert_eq!(human_bytes(0), "0 B");
+        assert_eq!(human_bytes(1023), "1023 B");
+        assert_eq!(human_bytes(1536), "1.5 KiB");
+        assert_eq!(human_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+}
@@ -0,0 +1,278 @@
+//! Dry run of date detection on a marker tree, showing where the date of each file would come
+//! from. Useful for checking `date-path` rules in config before scanning.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+
+use anyhow::{Context, Result};
+use exif::Reader as ExifReader;
+use path_slash::PathExt;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::dates::{self, DateSource, Timestamp};
+use crate::interlude::*;
+use crate::pathwalk::matcher;
+use crate::scanning::{date_candidates, fs_date_candidates, match_date_path, Tree};
+use crate::video;
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub marker: String,
+    pub files: Vec<Entry>,
+    pub rules: Vec<Rule>,
+    pub unparsable: Vec<Unparsable>,
+    /// Paths of files for which no date was found, grouped by directory.
+    pub undated: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub path: String,
+    /// Source of the date picked for the file according to `date-priority` config.
+    pub source: Option<DateSource>,
+    pub date: Option<Timestamp>,
+    /// Index of the `date-path` rule which yielded a date from the path, if any - even if the
+    /// picked date comes from another source.
+    pub rule: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Rule {
+    pub index: usize,
+    pub path: String,
+    pub date: String,
+    /// Number of files for which the rule yielded a date.
+    pub hits: u64,
+}
+
+/// A path matched by a rule, which however expanded to something not parseable as a date.
+#[derive(Debug, Serialize)]
+pub struct Unparsable {
+    pub path: String,
+    pub rule: usize,
+    pub expanded: String,
+}
+
+/// Detect dates of all files in `tree`, the same way as scanning does, without touching the DB.
+pub fn report(tree: &Tree, config: &Config) -> Result<Report> {
+    let mut rules: Vec<_> = tree
+        .date_paths
+        .iter()
+        .enumerate()
+        .map(|(index, date_path)| Rule {
+            index,
+            path: date_path.path.to_string(),
+            date: date_path.date.clone(),
+            hits: 0,
+        })
+        .collect();
+    let mut files = Vec::new();
+    let mut unparsable = Vec::new();
+    let mut undated = BTreeMap::<String, Vec<String>>::new();
+
+    let extensions = [&config.image_extensions[..], &config.video_extensions[..]].concat();
+    let video_matcher = matcher::CaseInsensitiveExtensions::boxed(&config.video_extensions);
+    let fs_dates = config.fs_date_fallback_at(&tree.marker);
+    for entry in tree.iter(&extensions) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                ieprintln!("Failed to access file, skipping: " err);
+                continue;
+            }
+        };
+        let os_relative = entry.relative_path();
+        let path = tree.root.join(os_relative);
+        let relative = os_relative
+            .to_slash()
+            .with_context(|| ifmt!("Failed to convert path " os_relative;? " to slash-based"))?;
+
+        let path_match = match_date_path(&relative, &tree.date_paths);
+        if let Some((i, _)) = path_match.date {
+            rules[i].hits += 1;
+        }
+        for (rule, expanded) in path_match.unparsable {
+            unparsable.push(Unparsable {
+                path: relative.clone(),
+                rule,
+                expanded,
+            });
+        }
+
+        // Only the metadata is read here, so this is much cheaper than a scan.
+        let mut candidates = if video_matcher.matches(&entry) {
+            let mut candidates = date_candidates(None, &relative, &tree.date_paths);
+            let created = File::open(&path)
+                .ok()
+                .and_then(|mut file| video::read_info(&mut file).ok())
+                .and_then(|video| video.created);
+            candidates.extend(created.map(|d| (DateSource::Video, Timestamp::from_utc(d))));
+            candidates
+        } else {
+            let exif = File::open(&path).ok().and_then(|file| {
+                ExifReader::new()
+                    .read_from_container(&mut BufReader::new(file))
+                    .ok()
+            });
+            date_candidates(exif.as_ref(), &relative, &tree.date_paths)
+        };
+        if fs_dates {
+            if let Ok(metadata) = fs::metadata(&path) {
+                candidates.extend(fs_date_candidates(&metadata));
+            }
+        }
+
+        let picked = dates::pick(&candidates, &config.date_priority);
+        if picked.is_none() {
+            let dir = match relative.rsplit_once('/') {
+                Some((dir, _)) => dir.to_string(),
+                None => String::new(),
+            };
+            undated.entry(dir).or_default().push(relative.clone());
+        }
+        files.push(Entry {
+            path: relative,
+            source: picked.map(|(source, _)| source),
+            date: picked.map(|(_, date)| date),
+            rule: path_match.date.map(|(i, _)| i),
+        });
+    }
+
+    Ok(Report {
+        marker: tree.marker.clone(),
+        files,
+        rules,
+        unparsable,
+        undated,
+    })
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "MARKER: {}", self.marker)?;
+        for entry in &self.files {
+            let date = entry.date.map_or("-".to_string(), |d| d.to_string());
+            let source = entry.source.map_or("-", DateSource::name);
+            let rule = entry
+                .rule
+                .map_or(String::new(), |i| format!(" [rule #{}]", i));
+            writeln!(f, "  {:26} {:13} {}{}", date, source, entry.path, rule)?;
+        }
+        writeln!(f, "Rules:")?;
+        for rule in &self.rules {
+            let hits = match rule.hits {
+                0 => "NEVER MATCHED".to_string(),
+                n => format!("{} hit(s)", n),
+            };
+            writeln!(
+                f,
+                "  #{} {:?} -> {:?}: {}",
+                rule.index, rule.path, rule.date, hits
+            )?;
+        }
+        if !self.unparsable.is_empty() {
+            writeln!(f, "Matches not parseable as dates:")?;
+            for u in &self.unparsable {
+                writeln!(f, "  rule #{} {:?}: {:?}", u.rule, u.path, u.expanded)?;
+            }
+        }
+        if !self.undated.is_empty() {
+            writeln!(f, "Undated files:")?;
+            for (dir, paths) in &self.undated {
+                writeln!(f, "  {}/ - {} file(s)", dir, paths.len())?;
+                for path in paths {
+                    writeln!(f, "    {}", path)?;
+                }
+            }
+        }
+        let undated: usize = self.undated.values().map(Vec::len).sum();
+        writeln!(
+            f,
+            "TOTAL: {} file(s), {} undated",
+            self.files.len(),
+            undated
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use regex::Regex;
+    use tempfile::tempdir;
+
+    use crate::config::DatePath;
+
+    use super::*;
+
+    #[test]
+    fn report_rule_hits_and_undated_files() {
+        // arrange
+
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("marker.json"), r#"{"id": "foo-marker"}"#).unwrap();
+        let rule = |path: &str, date: &str| DatePath {
+            path: Regex::new(path).unwrap(),
+            date: date.to_string(),
+        };
+        let mut config: Config = toml::from_str("markers.disk = []\n[date-path]").unwrap();
+        config.date_path.insert(
+            "foo-marker".to_string(),
+            vec![
+                rule(r"IMG_(\d{4})(\d\d)(\d\d)", "$1-$2-$3"),
+                rule(r"(\d{4})/(\d\d)/", "$1-$2-01"),
+                rule(r"never", "$0"),
+            ],
+        );
+        let tree = Tree::open(root.path().join("marker.json"), &config.date_path).unwrap();
+        for path in [
+            "2019/13/IMG_20190704.jpg",
+            "2019/13/scan.jpg",
+            "2018/02/x.png",
+            "old/y.jpg",
+        ] {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"not really an image").unwrap();
+        }
+
+        // act
+
+        let mut report = report(&tree, &config).unwrap();
+
+        // assert
+
+        report.files.sort_by(|a, b| a.path.cmp(&b.path));
+        let files: Vec<_> = report
+            .files
+            .iter()
+            .map(|e| (e.path.as_str(), e.source, e.rule))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                ("2018/02/x.png", Some(DateSource::DatePath), Some(1)),
+                (
+                    "2019/13/IMG_20190704.jpg",
+                    Some(DateSource::DatePath),
+                    Some(0)
+                ),
+                ("2019/13/scan.jpg", None, None),
+                ("old/y.jpg", None, None),
+            ]
+        );
+        let hits: Vec<_> = report.rules.iter().map(|r| r.hits).collect();
+        assert_eq!(hits, vec![1, 1, 0]);
+        assert_eq!(report.unparsable.len(), 1);
+        assert_eq!(report.unparsable[0].expanded, "2019-13-01");
+        assert_eq!(
+            report.undated,
+            BTreeMap::from([
+                ("2019/13".to_string(), vec!["2019/13/scan.jpg".to_string()]),
+                ("old".to_string(), vec!["old/y.jpg".to_string()]),
+            ])
+        );
+    }
+}
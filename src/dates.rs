@@ -0,0 +1,292 @@
+//! Choosing dates of files. Each location of a file can provide candidate dates from several
+//! sources (e.g. Exif of the original photo, and the path of its copy renamed by a messenger app).
+//! The effective date of the file is picked from all of them by a configurable priority of sources.
+
+use std::fmt;
+
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+
+/// Name of the tag marking files whose candidate dates disagree, so that they can be reviewed.
+pub const CONFLICT_TAG: &str = "date-conflict";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DateSource {
+    /// Exif `DateTimeOriginal`, i.e. when the photo was taken.
+    ExifOriginal,
+    /// Exif `DateTime`, i.e. when the file was last changed - often by the camera, when taking it.
+    ExifDatetime,
+    /// Creation time from the header of a video file.
+    Video,
+    /// Date extracted from the path of the file by a `date-path` rule.
+    DatePath,
+    /// Modification time of the file.
+    Mtime,
+    /// Creation time of the file, on filesystems which record it.
+    Birthtime,
+}
+
+impl DateSource {
+    pub const ALL: [DateSource; 6] = [
+        Self::ExifOriginal,
+        Self::ExifDatetime,
+        Self::Video,
+        Self::DatePath,
+        Self::Mtime,
+        Self::Birthtime,
+    ];
+
+    /// Name used in config and DB.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ExifOriginal => "exif-original",
+            Self::ExifDatetime => "exif-datetime",
+            Self::Video => "video",
+            Self::DatePath => "date-path",
+            Self::Mtime => "mtime",
+            Self::Birthtime => "birthtime",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    /// Timestamps of the file in filesystem, which easily change when the file is copied or
+    /// restored - so dates from them are of low confidence.
+    pub fn is_filesystem(self) -> bool {
+        matches!(self, Self::Mtime | Self::Birthtime)
+    }
+}
+
+/// Wall-clock time at which a file was created (with sub-second precision, if known), and the
+/// offset of the clock from UTC, if known.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Timestamp {
+    pub local: NaiveDateTime,
+    /// Offset from UTC in minutes.
+    pub offset: Option<i32>,
+}
+
+impl Timestamp {
+    /// Time in an unknown time zone.
+    pub fn naive(local: NaiveDateTime) -> Self {
+        Self {
+            local,
+            offset: None,
+        }
+    }
+
+    /// Time of an instant given in UTC, as shown by a clock in the time zone of this computer.
+    pub fn from_utc(utc: NaiveDateTime) -> Self {
+        let local = Local.from_utc_datetime(&utc);
+        Self {
+            local: local.naive_local(),
+            offset: Some(local.offset().local_minus_utc() / 60),
+        }
+    }
+
+    /// The instant in UTC, as accurately as possible: if the offset is unknown, the time zone of
+    /// this computer is assumed.
+    pub fn instant(&self) -> NaiveDateTime {
+        match self.offset {
+            Some(offset) => self.local - Duration::minutes(offset.into()),
+            None => Local
+                .from_local_datetime(&self.local)
+                .earliest()
+                .map_or(self.local, |d| d.naive_utc()),
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.local)?;
+        if let Some(offset) = self.offset {
+            let sign = if offset < 0 { '-' } else { '+' };
+            write!(
+                f,
+                " {}{:02}:{:02}",
+                sign,
+                offset.abs() / 60,
+                offset.abs() % 60
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Guess offset from UTC (in minutes) of a clock which showed `local` time at `utc`, e.g. of a
+/// camera clock at the time received from GPS. Time zones are offset by multiples of 15 minutes,
+/// so the difference is rounded to that - GPS time may be a bit stale.
+pub fn guess_offset(local: NaiveDateTime, utc: NaiveDateTime) -> Option<i32> {
+    let minutes = (local - utc).num_seconds() as f64 / 60.;
+    let offset = (minutes / 15.).round() as i32 * 15;
+    // Offsets of real time zones are between -12:00 and +14:00.
+    (-12 * 60..=14 * 60).contains(&offset).then_some(offset)
+}
+
+/// A date of a file, as claimed by a source.
+pub type Candidate = (DateSource, Timestamp);
+
+/// Modification times are often just the time of copying a file, so they're not trusted unless
+/// explicitly configured.
+pub fn default_priority() -> Vec<DateSource> {
+    vec![
+        DateSource::ExifOriginal,
+        DateSource::ExifDatetime,
+        DateSource::Video,
+        DateSource::DatePath,
+    ]
+}
+
+/// Candidate from the first source in `priority` which has any. If it has several (found at
+/// different locations), the earliest one is picked. If no source in `priority` has candidates,
+/// falls back to the earliest filesystem timestamp (which are recorded only on markers configured
+/// with `fs-date-fallback`). Implausible filesystem timestamps are ignored.
+pub fn pick(candidates: &[Candidate], priority: &[DateSource]) -> Option<Candidate> {
+    let usable: Vec<&Candidate> = candidates
+        .iter()
+        .filter(|(source, date)| !source.is_filesystem() || plausible(date.local))
+        .collect();
+    let earliest = |accept: &dyn Fn(DateSource) -> bool| {
+        usable
+            .iter()
+            .filter(|(source, _)| accept(*source))
+            .min_by_key(|(_, date)| date.instant())
+            .map(|candidate| **candidate)
+    };
+    priority
+        .iter()
+        .find_map(|wanted| earliest(&|source| source == *wanted))
+        .or_else(|| earliest(&DateSource::is_filesystem))
+}
+
+/// Whether a filesystem timestamp looks like a real date, and not e.g. an unset time reported as
+/// the epoch of a filesystem, or a time from a misconfigured clock.
+fn plausible(date: NaiveDateTime) -> bool {
+    // 1980-01-01 is the epoch of FAT filesystems, used by many cameras.
+    let min = NaiveDate::from_ymd(1980, 1, 2).and_hms(0, 0, 0);
+    let max = Local::now().naive_local() + Duration::days(1);
+    (min..=max).contains(&date)
+}
+
+/// Whether candidates from sources listed in `priority` differ by more than `max_spread`.
+pub fn conflicting(
+    candidates: &[Candidate],
+    priority: &[DateSource],
+    max_spread: Duration,
+) -> bool {
+    let dates = candidates
+        .iter()
+        .filter(|(source, _)| priority.contains(source))
+        .map(|(_, date)| date.instant());
+    match (dates.clone().min(), dates.max()) {
+        (Some(min), Some(max)) => max - min > max_spread,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pick_by_priority() {
+        let day = |d| Timestamp::naive(NaiveDate::from_ymd(2020, 5, d).and_hms(12, 0, 0));
+        let candidates = [
+            (DateSource::DatePath, day(3)),
+            (DateSource::ExifDatetime, day(9)),
+            (DateSource::Mtime, day(20)),
+            (DateSource::ExifDatetime, day(8)),
+        ];
+        let priority = default_priority();
+
+        assert_eq!(
+            pick(&candidates, &priority),
+            Some((DateSource::ExifDatetime, day(8)))
+        );
+        assert_eq!(
+            pick(&candidates, &[DateSource::Mtime, DateSource::DatePath]),
+            Some((DateSource::Mtime, day(20)))
+        );
+        // Filesystem timestamps are the fallback if none of the listed sources has a candidate.
+        assert_eq!(
+            pick(&candidates, &[DateSource::Video]),
+            Some((DateSource::Mtime, day(20)))
+        );
+
+        // Modification time is not in default priority, so it doesn't count as a conflict.
+        assert!(!conflicting(&candidates, &priority, Duration::days(6)));
+        assert!(conflicting(&candidates, &priority, Duration::days(5)));
+        assert!(!conflicting(&candidates[2..], &priority, Duration::zero()));
+    }
+
+    #[test]
+    fn fall_back_to_plausible_filesystem_dates() {
+        let date = |y| Timestamp::naive(NaiveDate::from_ymd(y, 1, 1).and_hms(12, 0, 0));
+        let priority = default_priority();
+        let mut candidates = vec![
+            (DateSource::Mtime, date(2015)),
+            (DateSource::Birthtime, date(1980)), // FAT epoch
+            (DateSource::Birthtime, date(2012)),
+            (DateSource::Mtime, date(9999)),
+        ];
+
+        assert_eq!(
+            pick(&candidates, &priority),
+            Some((DateSource::Birthtime, date(2012)))
+        );
+        candidates.push((DateSource::DatePath, date(2016)));
+        assert_eq!(
+            pick(&candidates, &priority),
+            Some((DateSource::DatePath, date(2016)))
+        );
+        assert_eq!(pick(&candidates[1..2], &priority), None);
+    }
+
+    #[test]
+    fn guess_offset_from_gps_time() {
+        let at = |h, m, s| NaiveDate::from_ymd(2019, 7, 1).and_hms(h, m, s);
+        assert_eq!(guess_offset(at(14, 0, 3), at(12, 1, 30)), Some(120));
+        assert_eq!(guess_offset(at(6, 30, 0), at(12, 0, 0)), Some(-330));
+        assert_eq!(
+            guess_offset(at(0, 0, 0), at(0, 0, 0) + Duration::days(2)),
+            None
+        );
+    }
+
+    #[test]
+    fn compare_instants_across_time_zones() {
+        let at = |h, offset| Timestamp {
+            local: NaiveDate::from_ymd(2019, 7, 1).and_hms(h, 0, 0),
+            offset,
+        };
+        // Photos taken by a phone in Warsaw (UTC+2), and a camera with clock set to UTC.
+        let candidates = [
+            (DateSource::ExifOriginal, at(14, Some(120))),
+            (DateSource::ExifOriginal, at(12, Some(0))),
+            (DateSource::ExifOriginal, at(13, Some(0))),
+        ];
+        assert_eq!(candidates[0].1.instant(), candidates[1].1.instant());
+        assert_eq!(
+            pick(&candidates[..1], &[DateSource::ExifOriginal]),
+            Some(candidates[0])
+        );
+        assert_eq!(
+            pick(&candidates[1..], &[DateSource::ExifOriginal]),
+            Some(candidates[1])
+        );
+        assert!(!conflicting(
+            &candidates[..2],
+            &[DateSource::ExifOriginal],
+            Duration::zero()
+        ));
+        assert!(conflicting(
+            &candidates,
+            &[DateSource::ExifOriginal],
+            Duration::minutes(59)
+        ));
+    }
+}
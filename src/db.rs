@@ -1,57 +1,255 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use anyhow::Result;
-use rusqlite::{params, Connection, Error::QueryReturnedNoRows};
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, NaiveDateTime};
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Action, Connection, Error::QueryReturnedNoRows};
 
+use crate::dates::{self, Candidate, DateSource, Timestamp};
+use crate::imaging;
 use crate::interlude::*;
+use crate::model::{CameraInfo, FileDetails, FileStat, Incident, THUMBNAIL_VERSION};
+use crate::query::{Clause, Query, Term};
 
 // TODO[LATER]: use Arc<RwLock<T>> instead of Arc<Mutex<T>>
 pub type SyncedDb = Arc<Mutex<Connection>>;
 
 pub fn open(path: impl AsRef<Path>) -> Result<SyncedDb> {
-    let db = Connection::open(path.as_ref())?;
+    let path = path.as_ref();
+    let db = Connection::open(path)?;
+    if needs_migration(&db)? {
+        backup(&db, path)?;
+    }
     init(&db)?;
     Ok(Arc::new(Mutex::new(db)))
 }
 
-pub fn init(db: &Connection) -> rusqlite::Result<()> {
-    db.execute_batch(
-        "
-          CREATE TABLE IF NOT EXISTS file (
-            hash TEXT UNIQUE NOT NULL
-              CHECK(length(hash) > 0),
-            date TEXT,
-            thumbnail BLOB
-          );
-          CREATE INDEX IF NOT EXISTS file_date ON file(date);
-
-          CREATE TABLE IF NOT EXISTS location (
-            file_id INTEGER NOT NULL,
-            backend_tag STRING NOT NULL, -- FIXME[LATER]: change to TEXT (https://stackoverflow.com/a/42264331/98528)
-            path STRING NOT NULL         -- FIXME[LATER]: change to TEXT (https://stackoverflow.com/a/42264331/98528)
-          );
-          CREATE INDEX IF NOT EXISTS
-            location_fileID ON location (file_id);
-          CREATE UNIQUE INDEX IF NOT EXISTS
-            location_perBackend ON location (backend_tag, path);
-
-          CREATE TABLE IF NOT EXISTS tag (
-            name TEXT UNIQUE NOT NULL
-              CHECK(length(name) > 0),
-            hidden BOOLEAN DEFAULT FALSE NOT NULL
-          );
-          INSERT INTO tag(name, hidden) VALUES
-              ('hidden', TRUE)
-            ON CONFLICT(name) DO NOTHING;
-
-          CREATE TABLE IF NOT EXISTS file_tag (
-            file_id INTEGER NOT NULL,
-            tag_id INTEGER NOT NULL
-          );
-          CREATE UNIQUE INDEX IF NOT EXISTS
-            file_tag_unique ON file_tag (file_id, tag_id);
-        ",
-    )
+/// Schema migrations, applied in order. Number of migrations already applied to a DB is stored
+/// as its `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    // 1: Initial schema. Note: DBs created before migrations were introduced already have it,
+    // but with user_version == 0, so all statements must be idempotent.
+    "
+      CREATE TABLE IF NOT EXISTS file (
+        hash TEXT UNIQUE NOT NULL
+          CHECK(length(hash) > 0),
+        date TEXT,
+        thumbnail BLOB
+      );
+      CREATE INDEX IF NOT EXISTS file_date ON file(date);
+
+      CREATE TABLE IF NOT EXISTS location (
+        file_id INTEGER NOT NULL,
+        backend_tag STRING NOT NULL,
+        path STRING NOT NULL
+      );
+      CREATE INDEX IF NOT EXISTS
+        location_fileID ON location (file_id);
+      CREATE UNIQUE INDEX IF NOT EXISTS
+        location_perBackend ON location (backend_tag, path);
+
+      CREATE TABLE IF NOT EXISTS tag (
+        name TEXT UNIQUE NOT NULL
+          CHECK(length(name) > 0),
+        hidden BOOLEAN DEFAULT FALSE NOT NULL
+      );
+      INSERT INTO tag(name, hidden) VALUES
+          ('hidden', TRUE)
+        ON CONFLICT(name) DO NOTHING;
+
+      CREATE TABLE IF NOT EXISTS file_tag (
+        file_id INTEGER NOT NULL,
+        tag_id INTEGER NOT NULL
+      );
+      CREATE UNIQUE INDEX IF NOT EXISTS
+        file_tag_unique ON file_tag (file_id, tag_id);
+    ",
+    // 2: Change `location` columns from STRING to TEXT (https://stackoverflow.com/a/42264331/98528).
+    // STRING has NUMERIC affinity, so some paths could have been stored as numbers - cast them back.
+    "
+      CREATE TABLE location_new (
+        file_id INTEGER NOT NULL,
+        backend_tag TEXT NOT NULL,
+        path TEXT NOT NULL
+      );
+      INSERT INTO location_new(rowid, file_id, backend_tag, path)
+        SELECT rowid, file_id, CAST(backend_tag AS TEXT), CAST(path AS TEXT)
+        FROM location;
+      DROP TABLE location;
+      ALTER TABLE location_new RENAME TO location;
+      CREATE INDEX location_fileID ON location (file_id);
+      CREATE UNIQUE INDEX location_perBackend ON location (backend_tag, path);
+    ",
+    // 3: Track version of thumbnail generation algorithm (see model::THUMBNAIL_VERSION), so that
+    // thumbnails created before Exif orientation was applied get regenerated.
+    "
+      ALTER TABLE file ADD COLUMN thumb_version INTEGER NOT NULL DEFAULT 0;
+    ",
+    // 4: Size of file contents in bytes.
+    "
+      ALTER TABLE file ADD COLUMN size INTEGER;
+    ",
+    // 5: Size and modification time of file at location, as seen during last scan (see
+    // model::FileStat), allowing to skip unchanged files.
+    "
+      ALTER TABLE location ADD COLUMN size INTEGER;
+      ALTER TABLE location ADD COLUMN mtime INTEGER;
+    ",
+    // 6: Duration of video files in milliseconds (NULL for images).
+    "
+      ALTER TABLE file ADD COLUMN duration_ms INTEGER;
+    ",
+    // 7: Integrity incidents - files found with contents not matching their hash in DB.
+    "
+      CREATE TABLE incident (
+        backend_tag TEXT NOT NULL,
+        path TEXT NOT NULL,
+        expected_hash TEXT NOT NULL,
+        actual_hash TEXT NOT NULL,
+        detected TEXT NOT NULL,
+        stat_changed BOOLEAN NOT NULL,
+        resolution TEXT
+      );
+      CREATE UNIQUE INDEX incident_open ON incident (backend_tag, path)
+        WHERE resolution IS NULL;
+    ",
+    // 8: Perceptual hash of images (see imaging::dhash), stored as i64, and their dimensions.
+    "
+      ALTER TABLE file ADD COLUMN phash INTEGER;
+      ALTER TABLE file ADD COLUMN width INTEGER;
+      ALTER TABLE file ADD COLUMN height INTEGER;
+    ",
+    // 9: Candidate dates of file at location, per source (see dates::DateSource). Location stats
+    // are forgotten, so that all files get fully scanned again, recording their candidates.
+    "
+      CREATE TABLE date_candidate (
+        backend_tag TEXT NOT NULL,
+        path TEXT NOT NULL,
+        source TEXT NOT NULL,
+        date TEXT NOT NULL
+      );
+      CREATE UNIQUE INDEX date_candidate_perSource ON date_candidate (backend_tag, path, source);
+      UPDATE location SET size = NULL, mtime = NULL;
+    ",
+    // 10: Source of the date picked for file (see dates::DateSource), if it was picked from
+    // candidates.
+    "
+      ALTER TABLE file ADD COLUMN date_source TEXT;
+    ",
+    // 11: UTC offsets (in minutes) of dates, if known, and the instant in UTC of file's date, by
+    // which files are sorted (see dates::Timestamp). Instants of known dates are computed assuming
+    // local time zone; location stats are forgotten, so that offsets get found by next scan.
+    "
+      ALTER TABLE date_candidate ADD COLUMN utc_offset INTEGER;
+      ALTER TABLE file ADD COLUMN utc_offset INTEGER;
+      ALTER TABLE file ADD COLUMN instant TEXT;
+      UPDATE file SET instant = datetime(date, 'utc');
+      CREATE INDEX file_instant ON file(instant);
+      UPDATE location SET size = NULL, mtime = NULL;
+    ",
+    // 12: GPS position of file from Exif (see model::GpsPosition), and name of the nearest place
+    // (see geocode::Places). Location stats are forgotten, so that positions get read by next scan.
+    "
+      ALTER TABLE file ADD COLUMN latitude REAL;
+      ALTER TABLE file ADD COLUMN longitude REAL;
+      ALTER TABLE file ADD COLUMN altitude REAL;
+      ALTER TABLE file ADD COLUMN place TEXT;
+      UPDATE location SET size = NULL, mtime = NULL;
+    ",
+    // 13: camera settings from Exif (see model::CameraInfo). Location stats are forgotten, so that
+    // they get read by next scan.
+    "
+      ALTER TABLE file ADD COLUMN make TEXT;
+      ALTER TABLE file ADD COLUMN model TEXT;
+      ALTER TABLE file ADD COLUMN lens TEXT;
+      ALTER TABLE file ADD COLUMN focal_length REAL;
+      ALTER TABLE file ADD COLUMN exposure REAL;
+      ALTER TABLE file ADD COLUMN iso INTEGER;
+      UPDATE location SET size = NULL, mtime = NULL;
+    ",
+    // 14: dimensions of thumbnail, so that the gallery can lay it out without decoding. Existing
+    // thumbnails are left without them, and get measured when shown (see thumbnails::load).
+    "
+      ALTER TABLE file ADD COLUMN thumb_width INTEGER;
+      ALTER TABLE file ADD COLUMN thumb_height INTEGER;
+    ",
+    // 15: whether file is a video, also when its duration is unknown. Files with neither duration
+    // nor thumbnail could be videos with unreadable headers, so they get rescanned.
+    "
+      ALTER TABLE file ADD COLUMN video INTEGER NOT NULL DEFAULT 0;
+      UPDATE file SET video = 1 WHERE duration_ms IS NOT NULL;
+      UPDATE location SET size = NULL, mtime = NULL
+        WHERE file_id IN (
+          SELECT rowid FROM file WHERE NOT video AND ifnull(length(thumbnail), 0) = 0
+        );
+    ",
+];
+
+/// Number of rows changed through connections set up by `init`, so that views of DB contents can
+/// tell when they're stale.
+static REVISION: AtomicU64 = AtomicU64::new(0);
+
+/// Current revision of DB contents - it changes whenever any row does.
+pub fn revision() -> u64 {
+    REVISION.load(Ordering::Relaxed)
+}
+
+/// Bring DB schema up to date, applying any missing migrations, and define custom SQL functions.
+pub fn init(db: &Connection) -> Result<()> {
+    db.update_hook(Some(|_: Action, _: &str, _: &str, _: i64| {
+        REVISION.fetch_add(1, Ordering::Relaxed);
+    }));
+    // SQLite's own `lower` only handles ASCII letters, so e.g. "KRAKÓW" wouldn't match "Kraków".
+    db.create_scalar_function(
+        "unicode_lower",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(ctx.get::<Option<String>>(0)?.map(|s| s.to_lowercase())),
+    )?;
+    let version = schema_version(db)?;
+    if version > MIGRATIONS.len() {
+        bail!(
+            "DB schema version {} is newer than supported by this program ({}), please upgrade",
+            version,
+            MIGRATIONS.len()
+        );
+    }
+    let tx = db.unchecked_transaction()?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)
+            .with_context(|| ifmt!("applying DB migration #" i + 1))?;
+    }
+    tx.pragma_update(None, "user_version", &(MIGRATIONS.len() as i64))?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn schema_version(db: &Connection) -> Result<usize> {
+    let version: i64 = db.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    Ok(version as usize)
+}
+
+/// Check if DB has any data and is in an old schema version.
+fn needs_migration(db: &Connection) -> Result<bool> {
+    let tables: u32 = db.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0))?;
+    Ok(tables > 0 && schema_version(db)? < MIGRATIONS.len())
+}
+
+/// Save a snapshot of the DB in a new file next to `path`.
+fn backup(db: &Connection, path: &Path) -> Result<()> {
+    let version = schema_version(db)?;
+    let now = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(ifmt!(".v" version "-" now ".bak"));
+    let backup = Path::new(&backup);
+    iprintln!("Backing up DB before migration to: " backup.display());
+    db.execute("VACUUM INTO ?", params![backup.to_string_lossy()])
+        .with_context(|| ifmt!("backing up DB to " backup;?))?;
+    Ok(())
 }
 
 pub fn exists(db: &Connection, marker: &str, relative: &str) -> ::rusqlite::Result<bool> {
@@ -64,27 +262,213 @@ pub fn exists(db: &Connection, marker: &str, relative: &str) -> ::rusqlite::Resu
     )
 }
 
-// FIXME[LATER]: somehow resolve if same hash at different locations gets attributed a different date
+/// Check if file at location has a thumbnail generated with an older algorithm than current.
+pub fn thumb_outdated(db: &Connection, marker: &str, relative: &str) -> ::rusqlite::Result<bool> {
+    db.query_row(
+        "SELECT COUNT(*) FROM location
+            JOIN file ON file.rowid = file_id
+            WHERE backend_tag = ?
+            AND path = ?
+            AND thumb_version < ?",
+        params![marker, relative, THUMBNAIL_VERSION],
+        |row| row.get(0),
+    )
+}
+
+/// Hash of file at location, if the location is known.
+pub fn location_hash(
+    db: &Connection,
+    marker: &str,
+    relative: &str,
+) -> ::rusqlite::Result<Option<String>> {
+    let hash = db.query_row(
+        "SELECT hash FROM location
+            JOIN file ON file.rowid = file_id
+            WHERE backend_tag = ?
+            AND path = ?",
+        params![marker, relative],
+        |row| row.get(0),
+    );
+    match hash {
+        Ok(hash) => Ok(Some(hash)),
+        Err(QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Size and modification time of file at location, as recorded during last scan. Returns None
+/// if location is unknown, or was not scanned yet with a version of backer recording it.
+pub fn location_stat(
+    db: &Connection,
+    marker: &str,
+    relative: &str,
+) -> ::rusqlite::Result<Option<FileStat>> {
+    let stat = db.query_row(
+        "SELECT size, mtime FROM location
+            WHERE backend_tag = ?
+            AND path = ?",
+        params![marker, relative],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    );
+    match stat {
+        Ok((Some(size), Some(mtime))) => Ok(Some(FileStat { size, mtime })),
+        Ok(_) | Err(QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn set_location_stat(
+    db: &Connection,
+    marker: &str,
+    relative: &str,
+    stat: &FileStat,
+) -> Result<()> {
+    db.execute(
+        "UPDATE location SET size = ?, mtime = ?
+            WHERE backend_tag = ?
+            AND path = ?",
+        params![stat.size, stat.mtime, marker, relative],
+    )?;
+    Ok(())
+}
+
+/// Add file at location to DB, or update it. Date of an already known file is kept - see
+/// `refresh_date` for choosing between dates found at different locations.
 pub fn upsert(
     db: &Connection,
     marker: &str,
     relative: &str,
     info: &crate::model::FileInfo,
 ) -> Result<()> {
+    let thumb_size = imaging::jpeg_dimensions(&info.thumb);
     db.execute(
-        "INSERT INTO file(hash,date,thumbnail) VALUES(?,?,?)
+        "INSERT INTO file(hash,date,instant,thumbnail,thumb_version,thumb_width,thumb_height,
+                size,duration_ms,video,phash,width,height,latitude,longitude,altitude,make,model,
+                lens,focal_length,exposure,iso)
+            VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
             ON CONFLICT(hash) DO UPDATE SET
                 date = ifnull(date, excluded.date),
-                thumbnail = excluded.thumbnail",
-        params![&info.hash, &info.date, &info.thumb],
+                instant = ifnull(instant, excluded.instant),
+                thumbnail = excluded.thumbnail,
+                thumb_version = excluded.thumb_version,
+                thumb_width = excluded.thumb_width,
+                thumb_height = excluded.thumb_height,
+                size = ifnull(excluded.size, size),
+                duration_ms = ifnull(excluded.duration_ms, duration_ms),
+                video = excluded.video,
+                phash = excluded.phash,
+                width = excluded.width,
+                height = excluded.height,
+                place = CASE WHEN latitude IS excluded.latitude AND longitude IS excluded.longitude
+                    THEN place END,
+                latitude = excluded.latitude,
+                longitude = excluded.longitude,
+                altitude = excluded.altitude,
+                make = excluded.make,
+                model = excluded.model,
+                lens = excluded.lens,
+                focal_length = excluded.focal_length,
+                exposure = excluded.exposure,
+                iso = excluded.iso",
+        params![
+            &info.hash,
+            &info.date,
+            &info.date.map(|d| Timestamp::naive(d).instant()),
+            &info.thumb,
+            THUMBNAIL_VERSION,
+            &thumb_size.map(|(w, _)| w),
+            &thumb_size.map(|(_, h)| h),
+            &info.size,
+            &info.duration_ms,
+            &info.video,
+            &info.phash.map(|h| h as i64),
+            &info.width,
+            &info.height,
+            &info.gps.map(|g| g.latitude),
+            &info.gps.map(|g| g.longitude),
+            &info.gps.and_then(|g| g.altitude),
+            &info.camera.make,
+            &info.camera.model,
+            &info.camera.lens,
+            &info.camera.focal_length,
+            &info.camera.exposure,
+            &info.camera.iso
+        ],
+    )?;
+    add_location(db, marker, relative, &info.hash)
+}
+
+/// Replace `legacy` hash of a file with the `current` one (see `scanning::Hashes`), keeping its
+/// locations and tags. If the file is already known under both hashes, the rows are merged.
+pub fn upgrade_hash(db: &Connection, legacy: &str, current: &str) -> Result<()> {
+    let ids = |hash| -> rusqlite::Result<Option<i64>> {
+        match db.query_row("SELECT rowid FROM file WHERE hash = ?", [hash], |row| {
+            row.get(0)
+        }) {
+            Ok(id) => Ok(Some(id)),
+            Err(QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err),
+        }
+    };
+    let old_id = match ids(legacy)? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let tx = db.unchecked_transaction()?;
+    match ids(current)? {
+        None => {
+            tx.execute(
+                "UPDATE file SET hash = ? WHERE rowid = ?",
+                params![current, old_id],
+            )?;
+        }
+        Some(new_id) => {
+            tx.execute(
+                "UPDATE file SET (date, utc_offset, instant, date_source) =
+                    (SELECT date, utc_offset, instant, date_source FROM file WHERE rowid = ?1)
+                    WHERE rowid = ?2 AND date IS NULL",
+                params![old_id, new_id],
+            )?;
+            tx.execute(
+                "UPDATE location SET file_id = ? WHERE file_id = ?",
+                params![new_id, old_id],
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO file_tag(file_id, tag_id)
+                    SELECT ?, tag_id FROM file_tag WHERE file_id = ?",
+                params![new_id, old_id],
+            )?;
+            tx.execute("DELETE FROM file_tag WHERE file_id = ?", params![old_id])?;
+            tx.execute("DELETE FROM file WHERE rowid = ?", params![old_id])?;
+        }
+    }
+    tx.execute(
+        "UPDATE incident SET expected_hash = ? WHERE expected_hash = ?",
+        params![current, legacy],
     )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Number of files still identified by a legacy SHA-1 hash (see `scanning::SHA256_PREFIX`).
+pub fn count_legacy_hashes(db: &Connection) -> Result<u32> {
+    let count = db.query_row(
+        "SELECT COUNT(*) FROM file WHERE hash NOT LIKE 'sha256:%'",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(count)
+}
+
+/// Record that file with given `hash`, already known in DB, is present at a location.
+pub fn add_location(db: &Connection, marker: &str, relative: &str, hash: &str) -> Result<()> {
     db.execute(
         "INSERT INTO location(file_id,backend_tag,path)
             SELECT rowid, ?, ? FROM file
               WHERE hash = ? LIMIT 1
             ON CONFLICT(backend_tag, path) DO UPDATE SET
               file_id = excluded.file_id",
-        params![&marker, &relative, &info.hash],
+        params![&marker, &relative, &hash],
     )?;
     Ok(())
 }
@@ -96,6 +480,547 @@ pub fn remove(db: &Connection, marker: &str, relative: &str) -> Result<()> {
             AND path = ?",
         params![&marker, &relative],
     )?;
+    db.execute(
+        "DELETE FROM date_candidate
+            WHERE backend_tag = ?
+            AND path = ?",
+        params![&marker, &relative],
+    )?;
+    Ok(())
+}
+
+/// Replace candidate dates of file at location with the ones found during a scan.
+pub fn set_date_candidates(
+    db: &Connection,
+    marker: &str,
+    relative: &str,
+    candidates: &[Candidate],
+) -> Result<()> {
+    let tx = db.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM date_candidate
+            WHERE backend_tag = ?
+            AND path = ?",
+        params![marker, relative],
+    )?;
+    for (source, date) in candidates {
+        tx.execute(
+            "INSERT INTO date_candidate(backend_tag,path,source,date,utc_offset)
+                VALUES(?,?,?,?,?)
+                ON CONFLICT(backend_tag, path, source) DO NOTHING",
+            params![marker, relative, source.name(), date.local, date.offset],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Candidate dates recorded at all `(marker, path)` locations of file with given `hash`.
+pub fn date_candidates_of(
+    db: &Connection,
+    hash: &str,
+) -> Result<Vec<((String, String), Candidate)>> {
+    let mut query = db.prepare_cached(
+        "SELECT c.backend_tag, c.path, source, c.date, c.utc_offset FROM date_candidate c
+            JOIN location USING (backend_tag, path)
+            JOIN file ON file.rowid = file_id
+            WHERE hash = ?
+            ORDER BY c.backend_tag, c.path, c.date",
+    )?;
+    let rows = query
+        .query_map(params![hash], |row| {
+            let source: String = row.get(2)?;
+            let date = Timestamp {
+                local: row.get(3)?,
+                offset: row.get(4)?,
+            };
+            Ok(((row.get(0)?, row.get(1)?), source, date))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    // Sources unknown to this version of backer are skipped.
+    let candidates = rows
+        .into_iter()
+        .filter_map(|(location, source, date)| {
+            DateSource::from_name(&source).map(|source| (location, (source, date)))
+        })
+        .collect();
+    Ok(candidates)
+}
+
+/// Set date of file with given `hash` to the one picked by `priority` from candidates at all its
+/// locations, along with its UTC offset and instant. If none can be picked, a date picked earlier
+/// is cleared (e.g. one from filesystem timestamps, no longer recorded), but a date found by old
+/// versions of backer, which didn't record candidates, is kept. Tag the file with
+/// `dates::CONFLICT_TAG` if the candidates differ by more than `max_spread`.
+pub fn refresh_date(
+    db: &Connection,
+    hash: &str,
+    priority: &[DateSource],
+    max_spread: chrono::Duration,
+) -> Result<()> {
+    let candidates: Vec<Candidate> = date_candidates_of(db, hash)?
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect();
+    match dates::pick(&candidates, priority) {
+        Some((source, date)) => db.execute(
+            "UPDATE file SET date = ?, utc_offset = ?, instant = ?, date_source = ?
+                WHERE hash = ?",
+            params![date.local, date.offset, date.instant(), source.name(), hash],
+        )?,
+        None => db.execute(
+            "UPDATE file SET date = NULL, utc_offset = NULL, instant = NULL, date_source = NULL
+                WHERE hash = ?
+                AND date_source IS NOT NULL",
+            params![hash],
+        )?,
+    };
+    let conflict = dates::conflicting(&candidates, priority, max_spread);
+    set_file_tag(db, hash, dates::CONFLICT_TAG, conflict)
+}
+
+/// Criteria selecting which files are shown in the gallery.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    /// Show only files carrying all of these tags.
+    pub include: Vec<String>,
+    /// Don't show files carrying any of these tags.
+    pub exclude: Vec<String>,
+    /// Show also files carrying tags marked as hidden (other than the ones explicitly included).
+    pub show_hidden: bool,
+    /// Query typed into the search box.
+    pub query: Query,
+}
+
+impl Filter {
+    /// SQL condition matching rows of the `file` table which pass the filter. Values are left as
+    /// `?` parameters, to be bound to `params()`.
+    pub fn sql(&self) -> String {
+        let mut conditions = Vec::new();
+        if !self.show_hidden {
+            conditions.push(ifmt!(
+                "file.rowid NOT IN (SELECT file_id FROM file_tag
+                    JOIN tag ON tag.rowid = tag_id
+                    WHERE tag.hidden AND tag.name NOT IN ("
+                    placeholders(self.included_tags().count()) "))"
+            ));
+        }
+        for tag in &self.include {
+            conditions.push(Term::Tag(tag.clone()).sql());
+        }
+        if !self.exclude.is_empty() {
+            conditions.push(ifmt!(
+                "file.rowid NOT IN (SELECT file_id FROM file_tag
+                    JOIN tag ON tag.rowid = tag_id
+                    WHERE tag.name IN (" placeholders(self.exclude.len()) "))"
+            ));
+        }
+        conditions.extend(self.query.clauses.iter().map(Clause::sql));
+        if conditions.is_empty() {
+            return "1".to_string();
+        }
+        conditions.join(" AND ")
+    }
+
+    /// Values of the `?` parameters in `sql()`, in order.
+    pub fn params(&self) -> Vec<Value> {
+        let mut params = Vec::new();
+        if !self.show_hidden {
+            params.extend(self.included_tags().map(|tag| Value::from(tag.clone())));
+        }
+        for tag in &self.include {
+            params.extend(Term::Tag(tag.clone()).params());
+        }
+        params.extend(self.exclude.iter().map(|tag| Value::from(tag.clone())));
+        params.extend(self.query.params());
+        params
+    }
+
+    /// Tags which files must carry to pass the filter.
+    fn included_tags(&self) -> impl Iterator<Item = &String> {
+        self.include.iter().chain(self.query.included_tags())
+    }
+
+    /// Update tag names in the filter after a tag was renamed (`new = Some(...)`) or deleted
+    /// (`new = None`).
+    pub fn rename_tag(&mut self, old: &str, new: Option<&str>) {
+        for list in [&mut self.include, &mut self.exclude] {
+            match new {
+                Some(new) => list
+                    .iter_mut()
+                    .filter(|t| *t == old)
+                    .for_each(|t| *t = new.to_string()),
+                None => list.retain(|t| t != old),
+            }
+        }
+    }
+}
+
+/// Comma-separated list of `n` SQL parameters.
+fn placeholders(n: usize) -> String {
+    vec!["?"; n].join(",")
+}
+
+/// Parameters of `filter.sql()`, followed by the `rest` of parameters of a query using it after
+/// the filter.
+pub fn with_params(
+    filter: &Filter,
+    rest: impl IntoIterator<Item = Value>,
+) -> impl rusqlite::Params {
+    params_from_iter(filter.params().into_iter().chain(rest))
+}
+
+/// Order of files in the gallery, in which offsets of files are counted. Files with equal instants
+/// (e.g. undated ones, or burst shots) are ordered by rowid, so that all queries agree on it.
+pub const FILE_ORDER: &str = "instant, file.rowid";
+
+/// Count files passing the `filter`.
+pub fn count_files(db: &Connection, filter: &Filter) -> Result<u32> {
+    let n = db.query_row(
+        &ifmt!("SELECT COUNT(*) FROM file WHERE " filter.sql()),
+        params_from_iter(filter.params()),
+        |row| row.get(0),
+    )?;
+    Ok(n)
+}
+
+/// List `(marker, path)` locations of the file at `offset` (in the order of dates, among files
+/// passing the `filter` - same as shown in the gallery).
+pub fn locations(db: &Connection, filter: &Filter, offset: u32) -> Result<Vec<(String, String)>> {
+    let locations = db
+        .prepare_cached(&ifmt!(
+            "SELECT backend_tag, path
+                FROM location
+                WHERE file_id = (SELECT rowid
+                    FROM file
+                    WHERE " filter.sql() "
+                    ORDER BY " FILE_ORDER "
+                    LIMIT 1 OFFSET ?)
+                ORDER BY backend_tag ASC, path ASC"
+        ))?
+        .query_map(with_params(filter, [offset.into()]), |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(locations)
+}
+
+/// Details of the file at `offset` (in the order of dates, among files passing the `filter` - same
+/// as shown in the gallery).
+pub fn details(db: &Connection, filter: &Filter, offset: u32) -> Result<Option<FileDetails>> {
+    let details = db.query_row(
+        &ifmt!(
+            "SELECT hash, date, size, duration_ms, width, height,
+                    make, model, lens, focal_length, exposure, iso, nullif(place, '')
+                FROM file
+                WHERE " filter.sql() "
+                ORDER BY " FILE_ORDER "
+                LIMIT 1 OFFSET ?"
+        ),
+        with_params(filter, [offset.into()]),
+        |row| {
+            Ok(FileDetails {
+                hash: row.get(0)?,
+                date: row.get(1)?,
+                size: row.get(2)?,
+                duration_ms: row.get(3)?,
+                width: row.get(4)?,
+                height: row.get(5)?,
+                camera: CameraInfo {
+                    make: row.get(6)?,
+                    model: row.get(7)?,
+                    lens: row.get(8)?,
+                    focal_length: row.get(9)?,
+                    exposure: row.get(10)?,
+                    iso: row.get(11)?,
+                },
+                place: row.get(12)?,
+            })
+        },
+    );
+    match details {
+        Ok(details) => Ok(Some(details)),
+        Err(QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Files from one month, among files passing a filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Month {
+    pub year: i32,
+    pub month: u32,
+    pub files: u32,
+    /// Offset of the first file from the month in the gallery.
+    pub offset: u32,
+}
+
+/// Months having any files passing the `filter`, in order.
+pub fn timeline(db: &Connection, filter: &Filter) -> Result<Vec<Month>> {
+    // Files are counted in a single pass in gallery order, so that the offset of a month is the
+    // offset of its first file.
+    let mut months = BTreeMap::new();
+    let mut query = db.prepare(&ifmt!(
+        "SELECT date FROM file WHERE " filter.sql() " ORDER BY " FILE_ORDER
+    ))?;
+    let mut rows = query.query(params_from_iter(filter.params()))?;
+    let mut offset = 0;
+    while let Some(row) = rows.next()? {
+        if let Some(date) = row.get::<_, Option<NaiveDateTime>>(0)? {
+            let (year, month) = (date.year(), date.month());
+            months
+                .entry((year, month))
+                .or_insert(Month {
+                    year,
+                    month,
+                    files: 0,
+                    offset,
+                })
+                .files += 1;
+        }
+        offset += 1;
+    }
+    Ok(months.into_values().collect())
+}
+
+/// Add or remove the tag named `tag` on all files in the `files` range of offsets (in the order
+/// of dates, among files passing the `filter` - same as shown in the gallery).
+pub fn tag_files(
+    db: &Connection,
+    tag: &str,
+    filter: &Filter,
+    files: RangeInclusive<u32>,
+    selected: bool,
+) -> Result<()> {
+    let limit = files.end() - files.start() + 1;
+    if selected {
+        db.execute(
+            &ifmt!(
+                "INSERT INTO file_tag(file_id, tag_id)
+                    SELECT f.rowid, tag.rowid
+                    FROM (SELECT rowid FROM file
+                        WHERE " filter.sql() "
+                        ORDER BY " FILE_ORDER "
+                        LIMIT ? OFFSET ?) AS f, tag
+                    WHERE tag.name = ?
+                    ON CONFLICT(file_id, tag_id) DO NOTHING"
+            ),
+            with_params(
+                filter,
+                [
+                    limit.into(),
+                    (*files.start()).into(),
+                    tag.to_string().into(),
+                ],
+            ),
+        )?;
+    } else {
+        db.execute(
+            &ifmt!(
+                "DELETE FROM file_tag
+                    WHERE file_id IN (SELECT rowid FROM file
+                        WHERE " filter.sql() "
+                        ORDER BY " FILE_ORDER "
+                        LIMIT ? OFFSET ?)
+                    AND tag_id = (SELECT rowid FROM tag WHERE name = ?)"
+            ),
+            with_params(
+                filter,
+                [
+                    limit.into(),
+                    (*files.start()).into(),
+                    tag.to_string().into(),
+                ],
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+/// Add or remove `tag` on a single file with given `hash`, creating the tag if needed.
+pub fn set_file_tag(db: &Connection, hash: &str, tag: &str, selected: bool) -> Result<()> {
+    if selected {
+        db.execute(
+            "INSERT INTO tag(name) VALUES(?) ON CONFLICT(name) DO NOTHING",
+            params![tag],
+        )?;
+        db.execute(
+            "INSERT INTO file_tag(file_id, tag_id)
+                SELECT file.rowid, tag.rowid FROM file, tag
+                WHERE file.hash = ? AND tag.name = ?
+                ON CONFLICT(file_id, tag_id) DO NOTHING",
+            params![hash, tag],
+        )?;
+    } else {
+        db.execute(
+            "DELETE FROM file_tag
+                WHERE file_id = (SELECT rowid FROM file WHERE hash = ?)
+                AND tag_id = (SELECT rowid FROM tag WHERE name = ?)",
+            params![hash, tag],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn set_tag_hidden(db: &Connection, tag: &str, hidden: bool) -> Result<()> {
+    db.execute(
+        "UPDATE tag SET hidden = ? WHERE name = ?",
+        params![hidden, tag],
+    )?;
+    Ok(())
+}
+
+pub fn create_tag(db: &Connection, tag: &str) -> Result<()> {
+    db.execute("INSERT INTO tag(name) VALUES(?)", params![tag])?;
+    Ok(())
+}
+
+pub fn rename_tag(db: &Connection, old: &str, new: &str) -> Result<()> {
+    db.execute("UPDATE tag SET name = ? WHERE name = ?", params![new, old])?;
+    Ok(())
+}
+
+/// Delete the tag named `tag`, untagging all files carrying it.
+pub fn delete_tag(db: &Connection, tag: &str) -> Result<()> {
+    let tx = db.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM file_tag
+            WHERE tag_id = (SELECT rowid FROM tag WHERE name = ?)",
+        params![tag],
+    )?;
+    tx.execute("DELETE FROM tag WHERE name = ?", params![tag])?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Hashes and dates of all files carrying `tag`, ordered by date.
+pub fn files_tagged(db: &Connection, tag: &str) -> Result<Vec<(String, Option<NaiveDateTime>)>> {
+    let mut query = db.prepare(
+        "SELECT hash, date FROM file
+            JOIN file_tag ON file_id = file.rowid
+            JOIN tag ON tag.rowid = tag_id
+            WHERE tag.name = ?
+            ORDER BY date, hash",
+    )?;
+    let files = query
+        .query_map(params![tag], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(files)
+}
+
+/// All `(marker, path)` locations of file with given `hash`.
+pub fn locations_of(db: &Connection, hash: &str) -> Result<Vec<(String, String)>> {
+    let mut query = db.prepare_cached(
+        "SELECT backend_tag, path FROM location
+            JOIN file ON file.rowid = file_id
+            WHERE hash = ?
+            ORDER BY backend_tag, path",
+    )?;
+    let locations = query
+        .query_map(params![hash], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(locations)
+}
+
+/// Record that file at location has contents not matching its hash in DB. If an incident is
+/// already open for the location, only its `actual_hash` is updated.
+pub fn add_incident(
+    db: &Connection,
+    marker: &str,
+    relative: &str,
+    expected_hash: &str,
+    actual_hash: &str,
+    stat_changed: bool,
+) -> Result<()> {
+    let now = chrono::Local::now().naive_local();
+    db.execute(
+        "INSERT INTO incident(backend_tag,path,expected_hash,actual_hash,detected,stat_changed)
+            VALUES(?,?,?,?,?,?)
+            ON CONFLICT(backend_tag, path) WHERE resolution IS NULL DO UPDATE SET
+              actual_hash = excluded.actual_hash",
+        params![
+            marker,
+            relative,
+            expected_hash,
+            actual_hash,
+            now,
+            stat_changed
+        ],
+    )?;
+    Ok(())
+}
+
+fn incident_from_row(row: &rusqlite::Row) -> rusqlite::Result<Incident> {
+    Ok(Incident {
+        id: row.get("rowid")?,
+        marker: row.get("backend_tag")?,
+        path: row.get("path")?,
+        expected_hash: row.get("expected_hash")?,
+        actual_hash: row.get("actual_hash")?,
+        detected: row.get("detected")?,
+        stat_changed: row.get("stat_changed")?,
+    })
+}
+
+/// All incidents not resolved yet, oldest first.
+pub fn open_incidents(db: &Connection) -> Result<Vec<Incident>> {
+    let mut query = db.prepare(
+        "SELECT rowid, * FROM incident
+            WHERE resolution IS NULL
+            ORDER BY detected, rowid",
+    )?;
+    let incidents = query
+        .query_map([], incident_from_row)?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(incidents)
+}
+
+pub fn open_incident(db: &Connection, id: i64) -> Result<Option<Incident>> {
+    let incident = db.query_row(
+        "SELECT rowid, * FROM incident
+            WHERE rowid = ?
+            AND resolution IS NULL",
+        params![id],
+        incident_from_row,
+    );
+    match incident {
+        Ok(incident) => Ok(Some(incident)),
+        Err(QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Check if there's an unresolved incident at location. Such files are not refreshed by scans,
+/// so that their expected hash is not forgotten.
+pub fn has_open_incident(db: &Connection, marker: &str, relative: &str) -> rusqlite::Result<bool> {
+    db.query_row(
+        "SELECT COUNT(*) FROM incident
+            WHERE backend_tag = ?
+            AND path = ?
+            AND resolution IS NULL",
+        params![marker, relative],
+        |row| row.get(0),
+    )
+}
+
+/// Mark incident as resolved, with `resolution` describing how.
+pub fn resolve_incident(db: &Connection, id: i64, resolution: &str) -> Result<()> {
+    db.execute(
+        "UPDATE incident SET resolution = ? WHERE rowid = ?",
+        params![resolution, id],
+    )?;
+    Ok(())
+}
+
+/// Forget size and modification time of file at location, so that it's fully scanned again
+/// during next scan.
+pub fn clear_location_stat(db: &Connection, marker: &str, relative: &str) -> Result<()> {
+    db.execute(
+        "UPDATE location SET size = NULL, mtime = NULL
+            WHERE backend_tag = ?
+            AND path = ?",
+        params![marker, relative],
+    )?;
     Ok(())
 }
 
@@ -142,21 +1067,49 @@ mod test {
     use chrono::NaiveDate;
 
     use crate::db;
-    use crate::model::FileInfo;
+    use crate::model::{CameraInfo, FileInfo, FileStat, GpsPosition};
+    use crate::query::Query;
 
     fn all_files(conn: &db::Connection) -> Vec<FileInfo> {
-        conn.prepare("SELECT hash, date, thumbnail FROM file")
-            .unwrap()
-            .query_map([], |row| {
-                Ok(FileInfo {
-                    hash: row.get_unwrap(0),
-                    date: row.get_unwrap(1),
-                    thumb: row.get_unwrap(2),
-                })
+        conn.prepare(
+            "SELECT hash, date, thumbnail, size, duration_ms, phash, width, height,
+                latitude, longitude, altitude, make, model, lens, focal_length, exposure, iso,
+                video
+            FROM file",
+        )
+        .unwrap()
+        .query_map([], |row| {
+            Ok(FileInfo {
+                hash: row.get_unwrap(0),
+                date: row.get_unwrap(1),
+                thumb: row.get_unwrap(2),
+                size: row.get_unwrap(3),
+                duration_ms: row.get_unwrap(4),
+                phash: row.get_unwrap::<_, Option<i64>>(5).map(|h| h as u64),
+                width: row.get_unwrap(6),
+                height: row.get_unwrap(7),
+                gps: row
+                    .get_unwrap::<_, Option<f64>>(8)
+                    .zip(row.get_unwrap::<_, Option<f64>>(9))
+                    .map(|(latitude, longitude)| GpsPosition {
+                        latitude,
+                        longitude,
+                        altitude: row.get_unwrap(10),
+                    }),
+                camera: CameraInfo {
+                    make: row.get_unwrap(11),
+                    model: row.get_unwrap(12),
+                    lens: row.get_unwrap(13),
+                    focal_length: row.get_unwrap(14),
+                    exposure: row.get_unwrap(15),
+                    iso: row.get_unwrap(16),
+                },
+                video: row.get_unwrap(17),
             })
-            .unwrap()
-            .map(|x| x.unwrap())
-            .collect()
+        })
+        .unwrap()
+        .map(|x| x.unwrap())
+        .collect()
     }
 
     #[test]
@@ -177,8 +1130,8 @@ mod test {
             path,
             &FileInfo {
                 hash: hash_a.clone(),
-                date: None,
                 thumb: vec![b'A'],
+                ..Default::default()
             },
         )
         .unwrap();
@@ -187,8 +1140,8 @@ mod test {
             all_files(&conn),
             vec![FileInfo {
                 hash: hash_a.clone(),
-                date: None,
-                thumb: vec![b'A']
+                thumb: vec![b'A'],
+                ..Default::default()
             }]
         );
 
@@ -202,6 +1155,7 @@ mod test {
                 hash: hash_b.clone(),
                 date: Some(date_2),
                 thumb: vec![b'B'],
+                ..Default::default()
             },
         )
         .unwrap();
@@ -214,13 +1168,14 @@ mod test {
             vec![
                 FileInfo {
                     hash: hash_a,
-                    date: None,
-                    thumb: vec![b'A']
+                    thumb: vec![b'A'],
+                    ..Default::default()
                 },
                 FileInfo {
                     hash: hash_b,
                     date: Some(date_2),
-                    thumb: vec![b'B']
+                    thumb: vec![b'B'],
+                    ..Default::default()
                 },
             ]
         );
@@ -243,8 +1198,8 @@ mod test {
             path,
             &FileInfo {
                 hash: hash.clone(),
-                date: None,
                 thumb: vec![b'A'],
+                ..Default::default()
             },
         )
         .unwrap();
@@ -253,8 +1208,8 @@ mod test {
             all_files(&conn),
             vec![FileInfo {
                 hash: hash.clone(),
-                date: None,
-                thumb: vec![b'A']
+                thumb: vec![b'A'],
+                ..Default::default()
             }]
         );
 
@@ -268,6 +1223,7 @@ mod test {
                 hash: hash.clone(),
                 date: Some(date_2),
                 thumb: vec![b'B'],
+                ..Default::default()
             },
         )
         .unwrap();
@@ -280,8 +1236,529 @@ mod test {
             vec![FileInfo {
                 hash,
                 date: Some(date_2),
-                thumb: vec![b'B']
+                thumb: vec![b'B'],
+                ..Default::default()
             }]
         );
     }
+
+    fn file_tags(conn: &db::Connection) -> Vec<(String, String)> {
+        conn.prepare(
+            "SELECT file.hash, tag.name FROM file_tag
+                JOIN file ON file.rowid = file_id
+                JOIN tag ON tag.rowid = tag_id
+                ORDER BY file.hash, tag.name",
+        )
+        .unwrap()
+        .query_map([], |row| Ok((row.get_unwrap(0), row.get_unwrap(1))))
+        .unwrap()
+        .map(|x| x.unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn tag_and_untag_files_range() {
+        // arrange
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        for (i, hash) in ["hash-a", "hash-b", "hash-c"].into_iter().enumerate() {
+            db::upsert(
+                &conn,
+                "foo-marker",
+                &format!("foo-dir/{}.jpeg", i),
+                &FileInfo {
+                    hash: hash.to_string(),
+                    date: Some(NaiveDate::from_ymd(2022, 1, 1 + i as u32).and_hms(0, 0, 0)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+
+        // act & assert
+
+        let show_all = db::Filter {
+            show_hidden: true,
+            ..Default::default()
+        };
+        db::tag_files(&conn, "hidden", &show_all, 1..=2, true).unwrap();
+        assert_eq!(
+            file_tags(&conn),
+            vec![
+                ("hash-b".to_string(), "hidden".to_string()),
+                ("hash-c".to_string(), "hidden".to_string()),
+            ]
+        );
+
+        // Tagging again must not duplicate rows.
+        db::tag_files(&conn, "hidden", &show_all, 0..=1, true).unwrap();
+        assert_eq!(file_tags(&conn).len(), 3);
+
+        db::tag_files(&conn, "hidden", &show_all, 0..=1, false).unwrap();
+        assert_eq!(
+            file_tags(&conn),
+            vec![("hash-c".to_string(), "hidden".to_string())]
+        );
+    }
+
+    #[test]
+    fn set_tag_hidden() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let hidden = |conn: &db::Connection| -> bool {
+            conn.query_row("SELECT hidden FROM tag WHERE name = 'hidden'", [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert!(hidden(&conn));
+        db::set_tag_hidden(&conn, "hidden", false).unwrap();
+        assert!(!hidden(&conn));
+    }
+
+    #[test]
+    fn create_rename_and_delete_tag() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        db::upsert(
+            &conn,
+            "foo-marker",
+            "foo-dir/file.jpeg",
+            &FileInfo {
+                hash: "fake-hash".to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        db::create_tag(&conn, "foo").unwrap();
+        assert!(db::create_tag(&conn, "foo").is_err());
+        db::tag_files(&conn, "foo", &Default::default(), 0..=0, true).unwrap();
+        db::rename_tag(&conn, "foo", "bar").unwrap();
+        assert_eq!(
+            file_tags(&conn),
+            vec![("fake-hash".to_string(), "bar".to_string())]
+        );
+
+        db::delete_tag(&conn, "bar").unwrap();
+        assert_eq!(file_tags(&conn), vec![]);
+        let n_tags: u32 = conn
+            .query_row("SELECT count(*) FROM tag", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(n_tags, 1); // only the default "hidden" tag
+    }
+
+    #[test]
+    fn filter_files_by_tags() {
+        // arrange
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        for (i, hash) in ["hash-a", "hash-b", "hash-c"].into_iter().enumerate() {
+            db::upsert(
+                &conn,
+                "foo-marker",
+                &format!("foo-dir/{}.jpeg", i),
+                &FileInfo {
+                    hash: hash.to_string(),
+                    date: Some(NaiveDate::from_ymd(2022, 1, 1 + i as u32).and_hms(0, 0, 0)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+        db::create_tag(&conn, "it's").unwrap();
+        db::tag_files(&conn, "hidden", &Default::default(), 0..=0, true).unwrap();
+        // Note: offsets now skip the hidden file "hash-a".
+        db::tag_files(&conn, "it's", &Default::default(), 0..=0, true).unwrap();
+
+        let count = |filter: db::Filter| db::count_files(&conn, &filter).unwrap();
+
+        // assert
+
+        assert_eq!(count(Default::default()), 2);
+        assert_eq!(
+            count(db::Filter {
+                show_hidden: true,
+                ..Default::default()
+            }),
+            3
+        );
+        assert_eq!(
+            count(db::Filter {
+                include: vec!["it's".to_string()],
+                ..Default::default()
+            }),
+            1
+        );
+        assert_eq!(
+            count(db::Filter {
+                exclude: vec!["it's".to_string()],
+                ..Default::default()
+            }),
+            1
+        );
+        // Explicitly included hidden tag shows its files.
+        assert_eq!(
+            count(db::Filter {
+                include: vec!["hidden".to_string()],
+                ..Default::default()
+            }),
+            1
+        );
+    }
+
+    #[test]
+    fn filter_files_by_camera_and_megapixels() {
+        // arrange
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let cameras = [
+            ("hash-a", "Canon", "Canon EOS 400D", 3888, 2592),
+            ("hash-b", "Apple", "iPhone 4", 2592, 1936),
+            ("hash-c", "Canon", "PowerShot A95", 1600, 1200),
+        ];
+        for (i, (hash, make, model, width, height)) in cameras.into_iter().enumerate() {
+            db::upsert(
+                &conn,
+                "foo-marker",
+                &format!("foo-dir/{}.jpeg", i),
+                &FileInfo {
+                    hash: hash.to_string(),
+                    date: Some(NaiveDate::from_ymd(2022, 1, 1 + i as u32).and_hms(0, 0, 0)),
+                    width: Some(width),
+                    height: Some(height),
+                    camera: CameraInfo {
+                        make: Some(make.to_string()),
+                        model: Some(model.to_string()),
+                        iso: Some(100),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+        let hashes = |text: &str| {
+            let filter = db::Filter {
+                query: Query::parse(text).unwrap(),
+                ..Default::default()
+            };
+            let n = db::count_files(&conn, &filter).unwrap();
+            (0..n)
+                .map(|i| db::details(&conn, &filter, i).unwrap().unwrap().hash)
+                .collect::<Vec<_>>()
+        };
+
+        // assert
+
+        assert_eq!(hashes("camera:canon"), vec!["hash-a", "hash-c"]);
+        assert_eq!(hashes(r#"camera:"canon powershot""#), vec!["hash-c"]);
+        assert_eq!(hashes("mp:..2"), vec!["hash-c"]);
+        assert_eq!(hashes("mp:2..10"), vec!["hash-b"]);
+        assert_eq!(hashes("-mp:2..10"), vec!["hash-a", "hash-c"]);
+        // Numbers are bound as parameters, so even huge ones make valid SQL.
+        assert_eq!(hashes("mp:1e303.."), Vec::<String>::new());
+        let details = db::details(&conn, &Default::default(), 1).unwrap().unwrap();
+        assert_eq!(details.camera.name().as_deref(), Some("Apple iPhone 4"));
+        assert_eq!(details.camera.iso, Some(100));
+        assert_eq!(db::details(&conn, &Default::default(), 3).unwrap(), None);
+    }
+
+    #[test]
+    fn timeline_of_months() {
+        // arrange
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let dates = [
+            Some((2019, 12, 31)),
+            None,
+            Some((2016, 6, 2)),
+            Some((2016, 6, 1)),
+            Some((2019, 12, 1)),
+            Some((2016, 8, 15)),
+        ];
+        for (i, date) in dates.into_iter().enumerate() {
+            let info = FileInfo {
+                hash: format!("hash-{}", i),
+                date: date.map(|(y, m, d)| NaiveDate::from_ymd(y, m, d).and_hms(12, 0, 0)),
+                ..Default::default()
+            };
+            db::upsert(&conn, "foo-marker", &format!("{}.jpg", i), &info).unwrap();
+        }
+        db::set_file_tag(&conn, "hash-3", "hidden", true).unwrap();
+
+        // act
+
+        let months = db::timeline(&conn, &Default::default()).unwrap();
+
+        // assert
+
+        let month = |year, month, files, offset| db::Month {
+            year,
+            month,
+            files,
+            offset,
+        };
+        assert_eq!(
+            months,
+            vec![
+                month(2016, 6, 1, 1),
+                month(2016, 8, 1, 2),
+                month(2019, 12, 2, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn migrate_unversioned_db() {
+        // arrange
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE file (hash TEXT UNIQUE NOT NULL, date TEXT, thumbnail BLOB);
+            CREATE TABLE location (
+                file_id INTEGER NOT NULL,
+                backend_tag STRING NOT NULL,
+                path STRING NOT NULL
+            );
+            INSERT INTO file(hash) VALUES ('fake-hash');
+            INSERT INTO location VALUES (1, 'foo-marker', '2022');",
+        )
+        .unwrap();
+        assert!(db::needs_migration(&conn).unwrap());
+
+        // act
+
+        db::init(&conn).unwrap();
+
+        // assert
+
+        assert!(!db::needs_migration(&conn).unwrap());
+        assert_eq!(db::schema_version(&conn).unwrap(), db::MIGRATIONS.len());
+        assert_eq!(db::exists(&conn, "foo-marker", "2022"), Ok(true));
+        let path_type: String = conn
+            .query_row("SELECT typeof(path) FROM location", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(path_type, "text");
+        // Re-running is a no-op.
+        db::init(&conn).unwrap();
+    }
+
+    #[test]
+    fn refuse_newer_db() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        conn.pragma_update(None, "user_version", &(db::MIGRATIONS.len() as i64 + 1))
+            .unwrap();
+        assert!(db::init(&conn).is_err());
+    }
+
+    #[test]
+    fn backup_before_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("backer.db");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE file (hash TEXT UNIQUE NOT NULL, date TEXT, thumbnail BLOB);",
+        )
+        .unwrap();
+        drop(conn);
+
+        db::open(&path).unwrap();
+
+        let backups: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("backer.db.v0-") && name.ends_with(".bak"))
+            .collect();
+        assert_eq!(backups.len(), 1, "files: {:?}", backups);
+    }
+
+    #[test]
+    fn thumb_outdated_after_migration() {
+        let marker: &str = "foo-marker";
+        let path: &str = "foo-dir/file.jpeg";
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        db::upsert(
+            &conn,
+            marker,
+            path,
+            &FileInfo {
+                hash: "fake-hash".to_string(),
+                thumb: vec![b'A'],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db::thumb_outdated(&conn, marker, path), Ok(false));
+
+        // Simulate thumbnail stored before migration.
+        conn.execute("UPDATE file SET thumb_version = 0", [])
+            .unwrap();
+        assert_eq!(db::thumb_outdated(&conn, marker, path), Ok(true));
+    }
+
+    #[test]
+    fn location_stat_roundtrip() {
+        let marker: &str = "foo-marker";
+        let path: &str = "foo-dir/file.jpeg";
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        assert_eq!(db::location_stat(&conn, marker, path), Ok(None));
+        db::upsert(
+            &conn,
+            marker,
+            path,
+            &FileInfo {
+                hash: "fake-hash".to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db::location_stat(&conn, marker, path), Ok(None));
+
+        let stat = FileStat {
+            size: 123,
+            mtime: 1_600_000_000_123_456_789,
+        };
+        db::set_location_stat(&conn, marker, path, &stat).unwrap();
+        assert_eq!(db::location_stat(&conn, marker, path), Ok(Some(stat)));
+    }
+
+    #[test]
+    fn add_and_resolve_incidents() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        db::add_incident(&conn, "marker-a", "foo.jpg", "hash-1", "hash-2", false).unwrap();
+        // Detecting the same problem again doesn't duplicate the incident.
+        db::add_incident(&conn, "marker-a", "foo.jpg", "hash-1", "hash-3", false).unwrap();
+        db::add_incident(&conn, "marker-b", "bar.jpg", "hash-4", "hash-5", true).unwrap();
+
+        let open = db::open_incidents(&conn).unwrap();
+        assert_eq!(open.len(), 2);
+        assert_eq!(
+            (open[0].marker.as_str(), open[0].actual_hash.as_str()),
+            ("marker-a", "hash-3")
+        );
+        assert!(open[1].stat_changed);
+        assert_eq!(
+            db::has_open_incident(&conn, "marker-a", "foo.jpg"),
+            Ok(true)
+        );
+
+        db::resolve_incident(&conn, open[0].id, "accepted").unwrap();
+        assert_eq!(
+            db::has_open_incident(&conn, "marker-a", "foo.jpg"),
+            Ok(false)
+        );
+        assert_eq!(db::open_incident(&conn, open[0].id).unwrap(), None);
+        assert_eq!(db::open_incidents(&conn).unwrap(), vec![open[1].clone()]);
+
+        // A new problem with the same file can be recorded after resolving the previous one.
+        db::add_incident(&conn, "marker-a", "foo.jpg", "hash-1", "hash-6", false).unwrap();
+        assert_eq!(db::open_incidents(&conn).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn upgrade_legacy_hashes() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let info = |hash: &str| FileInfo {
+            hash: hash.to_string(),
+            thumb: vec![b'A'],
+            ..Default::default()
+        };
+        db::upsert(&conn, "marker-a", "a.jpg", &info("sha1-x")).unwrap();
+        db::upsert(&conn, "marker-a", "b.jpg", &info("sha1-y")).unwrap();
+        db::upsert(&conn, "marker-b", "b.jpg", &info("sha256:y")).unwrap();
+        db::create_tag(&conn, "foo").unwrap();
+        db::tag_files(&conn, "foo", &Default::default(), 0..=2, true).unwrap();
+        assert_eq!(db::count_legacy_hashes(&conn).unwrap(), 2);
+
+        // act
+
+        db::upgrade_hash(&conn, "sha1-x", "sha256:x").unwrap();
+        db::upgrade_hash(&conn, "sha1-y", "sha256:y").unwrap();
+        db::upgrade_hash(&conn, "sha1-z", "sha256:z").unwrap();
+
+        // assert
+
+        assert_eq!(db::count_legacy_hashes(&conn).unwrap(), 0);
+        let hashes: Vec<_> = all_files(&conn).into_iter().map(|f| f.hash).collect();
+        assert_eq!(hashes, vec!["sha256:x", "sha256:y"]);
+        assert_eq!(
+            file_tags(&conn),
+            vec![
+                ("sha256:x".to_string(), "foo".to_string()),
+                ("sha256:y".to_string(), "foo".to_string()),
+            ]
+        );
+        let mut locations = db::locations_of(&conn, "sha256:y").unwrap();
+        locations.sort();
+        assert_eq!(
+            locations,
+            vec![
+                ("marker-a".to_string(), "b.jpg".to_string()),
+                ("marker-b".to_string(), "b.jpg".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn set_single_file_tag() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        for (path, hash) in [("a.jpg", "hash-a"), ("b.jpg", "hash-b")] {
+            let info = FileInfo {
+                hash: hash.to_string(),
+                ..Default::default()
+            };
+            db::upsert(&conn, "marker", path, &info).unwrap();
+        }
+
+        db::set_file_tag(&conn, "hash-b", "discard", true).unwrap();
+        assert_eq!(
+            file_tags(&conn),
+            vec![("hash-b".to_string(), "discard".to_string())]
+        );
+        db::set_file_tag(&conn, "hash-b", "discard", false).unwrap();
+        assert_eq!(file_tags(&conn), vec![]);
+    }
+
+    #[test]
+    fn undated_files_in_stable_order() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        for hash in ["hash-c", "hash-a", "hash-b"] {
+            let info = FileInfo {
+                hash: hash.to_string(),
+                ..Default::default()
+            };
+            db::upsert(&conn, "marker", &format!("{}.jpg", hash), &info).unwrap();
+        }
+        db::create_tag(&conn, "foo").unwrap();
+        let filter = db::Filter::default();
+
+        for offset in 0..3 {
+            let details = db::details(&conn, &filter, offset).unwrap().unwrap();
+            let locations = db::locations(&conn, &filter, offset).unwrap();
+            assert_eq!(locations[0].1, format!("{}.jpg", details.hash));
+            db::tag_files(&conn, "foo", &filter, offset..=offset, true).unwrap();
+            assert_eq!(
+                file_tags(&conn),
+                vec![(details.hash.clone(), "foo".to_string())]
+            );
+            db::tag_files(&conn, "foo", &filter, offset..=offset, false).unwrap();
+        }
+        // Files are in the order of adding them.
+        assert_eq!(
+            db::details(&conn, &filter, 0).unwrap().unwrap().hash,
+            "hash-c"
+        );
+    }
 }
@@ -0,0 +1,135 @@
+//! Finding clusters of near-duplicate images, i.e. files with different contents but similar
+//! perceptual hashes (e.g. an original photo, its recompressed copy and a resized export).
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use rusqlite::{params, Connection};
+
+/// Max number of differing bits between perceptual hashes of images considered duplicates.
+pub const MAX_DISTANCE: u32 = 6;
+
+/// Name of the tag marking lesser copies of duplicated images.
+pub const DISCARD_TAG: &str = "discard";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub hash: String,
+    pub thumb: Vec<u8>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub size: Option<u64>,
+    /// Whether the file is tagged with [`DISCARD_TAG`].
+    pub discarded: bool,
+}
+
+/// Find clusters of images with perceptual hashes at most `max_distance` bits apart. Clusters are
+/// sorted by size, and entries in them starting from biggest resolution and file size.
+pub fn clusters(db: &Connection, max_distance: u32) -> Result<Vec<Vec<Entry>>> {
+    let phashes = db
+        .prepare("SELECT rowid, phash FROM file WHERE phash IS NOT NULL")?
+        .query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?
+        .collect::<rusqlite::Result<Vec<(i64, u64)>>>()?;
+    let ids = group(&phashes, max_distance);
+
+    let mut query = db.prepare_cached(
+        "SELECT hash, thumbnail, width, height, size,
+            EXISTS(SELECT 1 FROM file_tag JOIN tag ON tag.rowid = tag_id
+                WHERE file_id = file.rowid AND tag.name = ?)
+        FROM file WHERE rowid = ?",
+    )?;
+    let mut clusters = ids
+        .into_iter()
+        .map(|ids| {
+            ids.into_iter()
+                .map(|id| {
+                    query.query_row(params![DISCARD_TAG, id], |row| {
+                        Ok(Entry {
+                            hash: row.get(0)?,
+                            thumb: row.get(1)?,
+                            width: row.get(2)?,
+                            height: row.get(3)?,
+                            size: row.get(4)?,
+                            discarded: row.get(5)?,
+                        })
+                    })
+                })
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for cluster in &mut clusters {
+        cluster.sort_by_key(|e| {
+            let pixels = e.width.unwrap_or(0) as u64 * e.height.unwrap_or(0) as u64;
+            std::cmp::Reverse((pixels, e.size))
+        });
+    }
+    clusters.sort_by_key(|c| std::cmp::Reverse(c.len()));
+    Ok(clusters)
+}
+
+/// Group ids of items with hashes at most `max_distance` bits apart (transitively). Returns only
+/// groups with more than one item.
+fn group(items: &[(i64, u64)], max_distance: u32) -> Vec<Vec<i64>> {
+    // If two hashes differ in at most `max_distance` bits, then after splitting them into
+    // `max_distance + 1` blocks, at least one block must be equal in both. So it's enough to
+    // compare hashes sharing a block, instead of all pairs.
+    let blocks = max_distance as usize + 1;
+    let mut buckets: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+    for (i, (_, hash)) in items.iter().enumerate() {
+        for b in 0..blocks {
+            let (start, end) = (b * 64 / blocks, (b + 1) * 64 / blocks);
+            let block = (hash >> start) & (u64::MAX >> (64 - (end - start)));
+            buckets.entry((b, block)).or_default().push(i);
+        }
+    }
+
+    let mut parents: Vec<usize> = (0..items.len()).collect();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+    for bucket in buckets.values() {
+        for (n, &i) in bucket.iter().enumerate() {
+            for &j in &bucket[n + 1..] {
+                if (items[i].1 ^ items[j].1).count_ones() <= max_distance {
+                    let (ri, rj) = (root(&mut parents, i), root(&mut parents, j));
+                    parents[ri] = rj;
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<i64>> = HashMap::new();
+    for (i, (id, _)) in items.iter().enumerate() {
+        let r = root(&mut parents, i);
+        groups.entry(r).or_default().push(*id);
+    }
+    let mut groups: Vec<_> = groups.into_values().filter(|g| g.len() > 1).collect();
+    for g in &mut groups {
+        g.sort_unstable();
+    }
+    groups.sort();
+    groups
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn group_similar_hashes() {
+        let items = [
+            (1, 0b1111_0000),
+            (2, 0b1111_0011),                // 2 bits from #1
+            (3, 0b1111_0011 | 0b111 << 40),  // 3 bits from #2, 5 from #1
+            (4, u64::MAX),                   // far from all
+            (5, u64::MAX ^ 0b1 << 63 ^ 0b1), // 2 bits from #4
+            (6, 0xABCD_0000_0000_0000),      // alone
+        ];
+        assert_eq!(group(&items, 3), vec![vec![1, 2, 3], vec![4, 5]]);
+        assert_eq!(group(&items, 1), Vec::<Vec<i64>>::new());
+    }
+}
@@ -0,0 +1,209 @@
+//! Offline reverse geocoding: naming places where photos were taken, using a local dump of places
+//! in the GeoNames format (e.g. `cities1000.txt` from https://download.geonames.org/export/dump/).
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+
+use crate::interlude::*;
+
+/// Positions farther than this from any known place are left without a place name.
+pub const MAX_DISTANCE_KM: f64 = 50.;
+
+const EARTH_RADIUS_KM: f64 = 6371.;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Place {
+    pub name: String,
+    pub country: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Place {
+    /// Name shown to the user and stored in DB, e.g. "Kraków, PL".
+    pub fn label(&self) -> String {
+        if self.country.is_empty() {
+            self.name.clone()
+        } else {
+            ifmt!(self.name ", " self.country)
+        }
+    }
+}
+
+/// Places indexed by cells of 1 degree of latitude and longitude, for fast lookup of the nearest.
+pub struct Places {
+    cells: HashMap<(i32, i32), Vec<Place>>,
+}
+
+impl Places {
+    pub fn read(path: &Path) -> Result<Self> {
+        let text =
+            fs::read_to_string(path).with_context(|| ifmt!("reading places file " path;?))?;
+        Self::parse(&text).with_context(|| ifmt!("parsing places file " path;?))
+    }
+
+    /// Parse tab-separated lines with columns as in GeoNames dumps: name in 2nd, latitude and
+    /// longitude in 5th and 6th, and country code in 9th.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut cells = HashMap::<_, Vec<_>>::new();
+        for (n, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let columns: Vec<&str> = line.split('\t').collect();
+            let column = |i: usize| {
+                columns
+                    .get(i)
+                    .copied()
+                    .with_context(|| ifmt!("line " n + 1 ": missing column " i + 1))
+            };
+            let coordinate = |i: usize| -> Result<f64> {
+                let v = column(i)?;
+                v.parse()
+                    .with_context(|| ifmt!("line " n + 1 ": bad coordinate " v;?))
+            };
+            let place = Place {
+                name: column(1)?.to_string(),
+                country: columns.get(8).copied().unwrap_or("").to_string(),
+                latitude: coordinate(4)?,
+                longitude: coordinate(5)?,
+            };
+            cells
+                .entry(cell(place.latitude, place.longitude))
+                .or_default()
+                .push(place);
+        }
+        Ok(Self { cells })
+    }
+
+    /// Place nearest to the position, if any is within [`MAX_DISTANCE_KM`].
+    pub fn nearest(&self, latitude: f64, longitude: f64) -> Option<&Place> {
+        // Cells get narrower towards the poles, so more of them must be checked there.
+        let (lat, lon) = cell(latitude, longitude);
+        let lat_cells = (MAX_DISTANCE_KM / 111.).ceil() as i32;
+        let lon_cells = match latitude.to_radians().cos() * 111. {
+            km if km > MAX_DISTANCE_KM => (MAX_DISTANCE_KM / km).ceil() as i32,
+            _ => 180,
+        };
+        let mut best: Option<(f64, &Place)> = None;
+        for dlat in -lat_cells..=lat_cells {
+            for dlon in -lon_cells..=lon_cells {
+                let lon = (lon + dlon + 180).rem_euclid(360) - 180;
+                for place in self.cells.get(&(lat + dlat, lon)).into_iter().flatten() {
+                    let d = distance_km(latitude, longitude, place.latitude, place.longitude);
+                    if d <= MAX_DISTANCE_KM && best.map_or(true, |(min, _)| d < min) {
+                        best = Some((d, place));
+                    }
+                }
+            }
+        }
+        best.map(|(_, place)| place)
+    }
+}
+
+fn cell(latitude: f64, longitude: f64) -> (i32, i32) {
+    (latitude.floor() as i32, longitude.floor() as i32)
+}
+
+/// Great-circle distance, by the haversine formula.
+fn distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let (dlat, dlon) = (lat2 - lat1, (lon2 - lon1).to_radians());
+    let a = (dlat / 2.).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.).sin().powi(2);
+    2. * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Name places of files having GPS position. Unless `refresh`, only files not looked up yet are
+/// processed. Files far from any known place get an empty name, so that they're not looked up
+/// again. Returns the number of files named.
+pub fn assign_places(db: &Connection, places: &Places, refresh: bool) -> Result<u64> {
+    let condition = if refresh { "1" } else { "place IS NULL" };
+    let positions = db
+        .prepare(&ifmt!(
+            "SELECT rowid, latitude, longitude FROM file
+            WHERE latitude IS NOT NULL AND longitude IS NOT NULL AND " condition
+        ))?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<Vec<(i64, f64, f64)>>>()?;
+    let mut update = db.prepare_cached("UPDATE file SET place = ? WHERE rowid = ?")?;
+    let mut named = 0;
+    for (id, latitude, longitude) in positions {
+        let place = places.nearest(latitude, longitude).map(Place::label);
+        named += place.is_some() as u64;
+        update.execute(params![place.unwrap_or_default(), id])?;
+    }
+    Ok(named)
+}
+
+/// Number of files having GPS position, with places not looked up yet.
+pub fn count_unnamed(db: &Connection) -> Result<u64> {
+    let n = db.query_row(
+        "SELECT count(*) FROM file
+            WHERE latitude IS NOT NULL AND longitude IS NOT NULL AND place IS NULL",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(n)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE: &str = "\
+3094802\tKraków\tKrakow\t\t50.06143\t19.93658\tP\tPPLA\tPL
+756135\tWarsaw\tWarsaw\t\t52.22977\t21.01178\tP\tPPLC\tPL
+2147714\tSydney\tSydney\t\t-33.86785\t151.20732\tP\tPPLA\tAU
+2198148\tWaiyevo\tWaiyevo\t\t-16.79194\t179.98222\tP\tPPL\tFJ
+";
+
+    #[test]
+    fn nearest_place() {
+        let places = Places::parse(SAMPLE).unwrap();
+        let name = |lat, lon| places.nearest(lat, lon).map(Place::label);
+
+        assert_eq!(name(50.05, 19.9), Some("Kraków, PL".to_string()));
+        assert_eq!(name(52.0, 20.8), Some("Warsaw, PL".to_string()));
+        assert_eq!(name(-33.9, 151.2), Some("Sydney, AU".to_string()));
+        // Across the antimeridian.
+        assert_eq!(name(-16.8, -179.9), Some("Waiyevo, FJ".to_string()));
+        // Between Kraków and Warsaw, too far from both.
+        assert_eq!(name(51.2, 20.5), None);
+        assert!(Places::parse("1\tNowhere\tx\t\tnorth\t0").is_err());
+    }
+
+    #[test]
+    fn assign_places_to_files() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO file(hash, latitude, longitude) VALUES
+                ('a', 50.07, 19.94), ('b', 0, 0), ('c', NULL, NULL)",
+        )
+        .unwrap();
+        let places = Places::parse(SAMPLE).unwrap();
+
+        assert_eq!(count_unnamed(&conn).unwrap(), 2);
+        assert_eq!(assign_places(&conn, &places, false).unwrap(), 1);
+        assert_eq!(count_unnamed(&conn).unwrap(), 0);
+        let names = conn
+            .prepare("SELECT hash, place FROM file ORDER BY hash")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<(String, Option<String>)>>>()
+            .unwrap();
+        assert_eq!(
+            names,
+            vec![
+                ("a".to_string(), Some("Kraków, PL".to_string())),
+                ("b".to_string(), Some(String::new())),
+                ("c".to_string(), None),
+            ]
+        );
+    }
+}
@@ -1,47 +1,93 @@
-use iced::pure::{row, scrollable, Application, Element};
+use std::path::{Path, PathBuf};
 
-use crate::db::SyncedDb;
+use anyhow::Result;
+use iced::keyboard::{self, KeyCode};
+use iced::pure::{button, column, row, text, text_input, Application, Element};
+use iced_native::event;
+use iced_native::image::Handle;
+use image::GenericImageView;
+
+use crate::config::Config;
+use crate::db::{self, SyncedDb};
+use crate::duplicates;
+use crate::imaging;
 use crate::interlude::*;
+use crate::model::FileDetails;
+use crate::query::Query;
+use crate::scanning::{connected_trees, find_file};
+use crate::thumbnails::{self, Thumbnails};
 use crate::widgets::{
+    duplicates::{self as duplicates_view, Duplicates},
     gallery::{self, Gallery},
+    info,
+    scroll::Scroll,
     tags::{self, tag},
+    timeline::{self, Timeline},
+    viewer::{self, Viewer},
 };
 
-// FIXME: duplicated between here and src/bin/view.rs !!!
 pub struct Gui {
     db: SyncedDb,
+    config: Config,
+    /// Thumbnails shown in the gallery, kept between redraws.
+    thumbnails: Arc<Thumbnails>,
     gallery_selection: gallery::Selection,
+    filter: db::Filter,
+    /// Text typed into the search box, and the error of parsing it as a query, if any. An invalid
+    /// query doesn't change the filter.
+    search: String,
+    search_error: Option<String>,
+    /// Details of the file selected in the gallery, if exactly one is selected.
+    details: Option<FileDetails>,
     tags: tags::Panel,
+    timeline: Timeline,
+    /// Number of the last request to scroll the gallery (see `Scroll::jump`), and the offset of
+    /// the file to scroll to.
+    jump: (u64, u32),
+    viewer: Option<Viewer>,
+    duplicates: Option<Duplicates>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     OfTags(tags::Event),
+    OfViewer(viewer::Event),
+    /// Original file at the offset was decoded in background for the viewer.
+    ViewerLoaded(u32, Result<Handle, String>),
+    OfDuplicates(duplicates_view::Event),
+    OfTimeline(timeline::Event),
+    ShowDuplicates,
+    EditSearch(String),
     GallerySelection(gallery::Selection),
+    GalleryOpen(u32),
+    /// Thumbnails were loaded in background, so the gallery needs redrawing.
+    ThumbnailsLoaded,
+    KeyPressed(KeyCode),
 }
 
 impl Application for Gui {
     type Message = Message;
-    type Flags = SyncedDb;
+    type Flags = (SyncedDb, Config);
     type Executor = iced::executor::Default;
 
-    fn new(db: SyncedDb) -> (Gui, iced::Command<Self::Message>) {
-        let gui = Gui {
+    fn new((db, config): Self::Flags) -> (Gui, iced::Command<Self::Message>) {
+        let mut gui = Gui {
             db: Arc::clone(&db),
+            config,
+            thumbnails: Arc::new(Thumbnails::new(Arc::clone(&db), thumbnails::CAPACITY)),
             gallery_selection: Default::default(),
-            tags: tags::Panel::new(&[
-                tag::Tag {
-                    name: "hidden".to_string(),
-                    selected: None,
-                    hidden: true,
-                },
-                tag::Tag {
-                    name: "tag 2".to_string(),
-                    selected: Some(true),
-                    hidden: false,
-                },
-            ]),
+            filter: Default::default(),
+            search: String::new(),
+            search_error: None,
+            details: None,
+            tags: tags::Panel::new(&[]),
+            timeline: Timeline::new(Vec::new()),
+            jump: (0, 0),
+            viewer: None,
+            duplicates: None,
         };
+        gui.load_tags_for_selection();
+        gui.load_timeline();
         (gui, iced::Command::none())
     }
 
@@ -50,82 +96,377 @@ impl Application for Gui {
     }
 
     fn update(&mut self, message: Self::Message) -> iced::Command<Self::Message> {
-        // TODO: when GallerySelect received, save file IDs, so that their tags can be easily
-        // updated in DB when a tag is toggled
         match message {
-            Message::OfTags(m) => {
-                self.tags.update(m);
-                self.load_tags_for_selection();
-            }
+            Message::OfTags(m) => match self.save_tags_event(&m) {
+                Err(err) => {
+                    ieprintln!("Error saving tags: " error_chain(&err) ".");
+                    // Show the tags as they are in DB, not as the failed change would make them.
+                    self.load_tags_for_selection();
+                }
+                Ok(saved) => {
+                    let filtered = self.update_filter(&m);
+                    self.tags.update(m);
+                    if filtered {
+                        self.gallery_selection = Default::default();
+                    }
+                    if saved || filtered {
+                        self.load_tags_for_selection();
+                        self.load_timeline();
+                    }
+                }
+            },
             Message::GallerySelection(selection) => {
                 self.gallery_selection = selection;
                 self.load_tags_for_selection();
             }
+            Message::OfTimeline(event) => {
+                let timeline::Event::Jump { offset, .. } = event;
+                self.jump = (self.jump.0 + 1, offset);
+                self.timeline.update(event);
+            }
+            Message::GalleryOpen(offset) => return self.open_viewer(offset),
+            Message::ThumbnailsLoaded => {}
+            Message::ViewerLoaded(offset, image) => {
+                // The user may have moved on to another file meanwhile.
+                if let Some(viewer) = self.viewer.as_mut().filter(|v| v.offset == offset) {
+                    viewer.set_image(image);
+                }
+            }
+            Message::OfViewer(event) => {
+                let offset = match &self.viewer {
+                    Some(viewer) => viewer.offset,
+                    None => return iced::Command::none(),
+                };
+                match event {
+                    viewer::Event::Previous => return self.open_viewer(offset.saturating_sub(1)),
+                    viewer::Event::Next => return self.open_viewer(offset + 1),
+                    viewer::Event::Close => self.viewer = None,
+                }
+            }
+            Message::ShowDuplicates => {
+                let clusters =
+                    duplicates::clusters(&self.db.lock().unwrap(), duplicates::MAX_DISTANCE);
+                match clusters {
+                    Ok(clusters) => self.duplicates = Some(Duplicates::new(clusters)),
+                    Err(err) => ieprintln!("Error finding duplicates: " error_chain(&err) "."),
+                }
+            }
+            Message::EditSearch(text) => {
+                match Query::parse(&text) {
+                    Ok(query) if query != self.filter.query => {
+                        self.filter.query = query;
+                        self.search_error = None;
+                        self.gallery_selection = Default::default();
+                        self.load_tags_for_selection();
+                        self.load_timeline();
+                    }
+                    Ok(_) => self.search_error = None,
+                    Err(err) => self.search_error = Some(error_chain(&err)),
+                }
+                self.search = text;
+            }
+            Message::OfDuplicates(duplicates_view::Event::Close) => {
+                self.duplicates = None;
+                self.load_tags_for_selection();
+                self.load_timeline();
+            }
+            Message::OfDuplicates(event) => {
+                if let Err(err) = self.save_duplicates_event(&event) {
+                    ieprintln!("Error saving tags: " error_chain(&err) ".");
+                } else if let Some(duplicates) = &mut self.duplicates {
+                    duplicates.update(event);
+                }
+            }
+            Message::KeyPressed(key_code) => {
+                let event = match (&self.viewer, key_code) {
+                    (None, KeyCode::Enter | KeyCode::NumpadEnter) => {
+                        let offset = *self.gallery_selection.range().start();
+                        return self.update(Message::GalleryOpen(offset));
+                    }
+                    (Some(_), KeyCode::Escape) => viewer::Event::Close,
+                    (Some(_), KeyCode::Left) => viewer::Event::Previous,
+                    (Some(viewer), KeyCode::Right) if viewer.has_next() => viewer::Event::Next,
+                    _ => return iced::Command::none(),
+                };
+                return self.update(Message::OfViewer(event));
+            }
         }
         iced::Command::none()
     }
 
-    fn view(&self) -> Element<Self::Message> {
-        // FIXME: Milestone: detect click
-        // FIXME: Milestone: add preview window on click
+    fn subscription(&self) -> iced::Subscription<Self::Message> {
+        // Only pass keys not already handled by widgets (e.g. text inputs).
+        let keys = iced_native::subscription::events_with(|event, status| match (event, status) {
+            (
+                iced_native::Event::Keyboard(keyboard::Event::KeyPressed { key_code, .. }),
+                event::Status::Ignored,
+            ) => Some(Message::KeyPressed(key_code)),
+            _ => None,
+        });
+        let thumbnails = self.thumbnails.loaded().map(|()| Message::ThumbnailsLoaded);
+        iced::Subscription::batch([keys, thumbnails])
+    }
+
+    fn view(&self) -> Element<'_, Self::Message> {
         // FIXME: Milestone: show some info about where img is present
 
-        let gallery = Gallery::new(Arc::clone(&self.db))
-            .with_selection(self.gallery_selection)
-            .on_select(Message::GallerySelection);
+        if let Some(duplicates) = &self.duplicates {
+            return duplicates.view().map(Message::OfDuplicates);
+        }
         let tags = self.tags.view().map(Message::OfTags);
-        row()
-            .push(
-                scrollable(gallery), // // .height(iced::Length::Fill)
-                                     // .width(iced::Length::Fill),
-            )
-            .push(tags)
-            .into()
+        let details = match &self.details {
+            Some(details) => info::view(details),
+            None => column().into(),
+        };
+        if let Some(viewer) = &self.viewer {
+            // TODO[LATER]: keep gallery's scroll position when returning from viewer
+            return row()
+                .push(viewer.view().map(Message::OfViewer))
+                .push(column().spacing(20).push(tags).push(details))
+                .into();
+        }
+        let gallery = Gallery::new(Arc::clone(&self.db), Arc::clone(&self.thumbnails))
+            .with_selection(self.gallery_selection)
+            .with_filter(self.filter.clone())
+            .on_select(Message::GallerySelection)
+            .on_open(Message::GalleryOpen);
+        let (jump, offset) = self.jump;
+        let row_y = gallery.row_y(offset);
+        let side = column().spacing(20).push(tags).push(details).push(
+            button(text("Find duplicates"))
+                .on_press(Message::ShowDuplicates)
+                .padding(10),
+        );
+        let search = text_input(
+            "Search, e.g.: tag:holiday date:2019-06..2019-08 camera:canon mp:8.. -path:*signal*",
+            &self.search,
+            Message::EditSearch,
+        )
+        .padding(10);
+        let main = column()
+            .spacing(10)
+            .push(search)
+            .push(text(self.search_error.as_deref().unwrap_or("")).size(16))
+            .push(Scroll::new(gallery).jump(jump, row_y));
+        let timeline = column()
+            .width(iced::Length::Units(130))
+            .push(self.timeline.view().map(Message::OfTimeline));
+        row().push(main).push(timeline).push(side).into()
     }
 }
 
 impl Gui {
+    /// Show file at `offset` in gallery order in full-size viewer, and select it in the gallery.
+    /// The original file is decoded in background, as large images take a while.
+    fn open_viewer(&mut self, offset: u32) -> iced::Command<Message> {
+        let db = self.db.lock().unwrap();
+        let (locations, count) = match db::locations(&db, &self.filter, offset)
+            .and_then(|locations| Ok((locations, db::count_files(&db, &self.filter)?)))
+        {
+            Ok(found) => found,
+            Err(err) => {
+                ieprintln!("Error opening file: " error_chain(&err) ".");
+                return iced::Command::none();
+            }
+        };
+        drop(db);
+        if offset >= count {
+            return iced::Command::none();
+        }
+        let original = self.find_original(&locations);
+        self.viewer = Some(Viewer::new(offset, original.clone(), offset + 1 < count));
+        self.gallery_selection = gallery::Selection::single(offset);
+        self.load_tags_for_selection();
+        match original {
+            Some(path) => {
+                iced::Command::perform(async move { decode_original(&path) }, move |image| {
+                    Message::ViewerLoaded(offset, image)
+                })
+            }
+            None => iced::Command::none(),
+        }
+    }
+
+    /// Find first of the `(marker, path)` locations that exists on a currently connected disk.
+    fn find_original(&self, locations: &[(String, String)]) -> Option<PathBuf> {
+        let trees = connected_trees(&self.config);
+        find_file(&trees, locations).map(|(_, path)| path)
+    }
+
+    /// Persist changes requested in tags panel to DB. Returns true if anything was changed in
+    /// the DB.
+    fn save_tags_event(&self, event: &tags::Event) -> Result<bool> {
+        let db = self.db.lock().unwrap();
+        match event {
+            tags::Event::OfNthTag(i, tag_event) => {
+                let name = match self.tags.get(*i) {
+                    Some(tag) => &tag.name,
+                    None => return Ok(false),
+                };
+                match tag_event {
+                    tag::Event::SetSelected(selected) => {
+                        let selection = self.gallery_selection.range();
+                        db::tag_files(&db, name, &self.filter, selection, *selected)?
+                    }
+                    tag::Event::SetHidden(hidden) => db::set_tag_hidden(&db, name, *hidden)?,
+                    tag::Event::Rename(new_name)
+                        if self.tags.name_available(new_name, Some(*i)) =>
+                    {
+                        db::rename_tag(&db, name, new_name.trim())?
+                    }
+                    tag::Event::Delete => db::delete_tag(&db, name)?,
+                    _ => return Ok(false),
+                }
+            }
+            tags::Event::Create(name) if self.tags.name_available(name, None) => {
+                db::create_tag(&db, name.trim())?
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Persist changes requested in duplicates view to DB.
+    fn save_duplicates_event(&self, event: &duplicates_view::Event) -> Result<()> {
+        if let duplicates_view::Event::SetDiscarded(i, n, discarded) = event {
+            let entry = match self.duplicates.as_ref().and_then(|d| d.get(*i, *n)) {
+                Some(entry) => entry,
+                None => return Ok(()),
+            };
+            let db = self.db.lock().unwrap();
+            db::set_file_tag(&db, &entry.hash, duplicates::DISCARD_TAG, *discarded)?;
+        }
+        Ok(())
+    }
+
+    /// Apply changes requested in tags panel to the gallery filter. Returns true if the set of
+    /// files shown in the gallery could have changed.
+    fn update_filter(&mut self, event: &tags::Event) -> bool {
+        let old = self.filter.clone();
+        match event {
+            tags::Event::SetShowHidden(show_hidden) => self.filter.show_hidden = *show_hidden,
+            tags::Event::OfNthTag(i, tag_event) => {
+                let name = match self.tags.get(*i) {
+                    Some(tag) => tag.name.clone(),
+                    None => return false,
+                };
+                match tag_event {
+                    tag::Event::SetFilter(filter) => {
+                        self.filter.rename_tag(&name, None);
+                        match filter {
+                            Some(true) => self.filter.include.push(name),
+                            Some(false) => self.filter.exclude.push(name),
+                            None => {}
+                        }
+                    }
+                    tag::Event::SetHidden(_) => return true,
+                    tag::Event::Rename(new_name)
+                        if self.tags.name_available(new_name, Some(*i)) =>
+                    {
+                        self.filter.rename_tag(&name, Some(new_name.trim()))
+                    }
+                    tag::Event::Delete => self.filter.rename_tag(&name, None),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        self.filter != old
+    }
+
+    fn load_timeline(&mut self) {
+        let months = match db::timeline(&self.db.lock().unwrap(), &self.filter) {
+            Ok(months) => months,
+            Err(err) => {
+                ieprintln!("Error loading timeline: " error_chain(&err) ".");
+                return;
+            }
+        };
+        self.timeline = Timeline::new(months).with_expanded(self.timeline.expanded());
+    }
+
     fn load_tags_for_selection(&mut self) {
         let db = self.db.lock().unwrap();
-        let sql = r"
-SELECT tag.name, tag.hidden, count(ttt)
+        // With no files, there's no selection to show tags of.
+        match db::count_files(&db, &self.filter) {
+            Ok(0) => {
+                self.tags = tags::Panel::new(&[]).with_show_hidden(self.filter.show_hidden);
+                self.details = None;
+                return;
+            }
+            Ok(_) => {}
+            Err(err) => ieprintln!("Error counting files: " error_chain(&err) "."),
+        }
+        let sql = ifmt!(
+            r"
+SELECT tag.name, tag.hidden, count(ttt),
+    (SELECT count(*) FROM file_tag WHERE tag_id = tag.rowid)
 FROM tag LEFT JOIN (
     SELECT tag_id AS ttt
     FROM file_tag
     WHERE file_id IN (
         SELECT rowid
         FROM file
-        ORDER BY date
+        WHERE " self.filter.sql() "
+        ORDER BY " db::FILE_ORDER "
         LIMIT ? OFFSET ?
     )
 ) ON tag.rowid = ttt
-GROUP BY tag.rowid";
-        // FIXME: make it work when there are 0 images total in DB
+GROUP BY tag.rowid"
+        );
         let selection = self.gallery_selection.range();
         let limit = selection.end() - selection.start() + 1;
-        println!("NEW TAGS for: {} .. {}", selection.start(), limit);
-        self.tags = db
-            .prepare_cached(sql)
+        let tags: Vec<_> = db
+            .prepare_cached(&sql)
             .unwrap()
-            .query_map([limit, *selection.start()], |row| {
-                let name: String = row.get_unwrap(0);
-                let hidden: bool = row.get_unwrap(1);
-                let count: u32 = row.get_unwrap(2);
-                let selected = if count == 0 {
-                    Some(false)
-                } else if count == limit {
-                    Some(true)
-                } else {
-                    None
-                };
-                Ok(tag::Tag {
-                    name,
-                    hidden,
-                    selected,
-                })
-            })
+            .query_map(
+                db::with_params(&self.filter, [limit.into(), (*selection.start()).into()]),
+                |row| {
+                    let name: String = row.get_unwrap(0);
+                    let hidden: bool = row.get_unwrap(1);
+                    let count: u32 = row.get_unwrap(2);
+                    let files: u32 = row.get_unwrap(3);
+                    let selected = if count == 0 {
+                        Some(false)
+                    } else if count == limit {
+                        Some(true)
+                    } else {
+                        None
+                    };
+                    let mut tag = tag::Tag::new(name, selected, hidden, files);
+                    if self.filter.include.contains(&tag.name) {
+                        tag.filter = Some(true);
+                    } else if self.filter.exclude.contains(&tag.name) {
+                        tag.filter = Some(false);
+                    }
+                    Ok(tag)
+                },
+            )
             .unwrap()
             .map(|x| x.unwrap())
             .collect();
+        self.tags = tags::Panel::new(&tags).with_show_hidden(self.filter.show_hidden);
+
+        self.details = match limit {
+            1 => db::details(&db, &self.filter, *selection.start()).unwrap_or_else(|err| {
+                ieprintln!("Error loading file details: " error_chain(&err) ".");
+                None
+            }),
+            _ => None,
+        };
+    }
+}
+
+/// Decode the original file at `path` for the viewer, or describe why it couldn't be.
+fn decode_original(path: &Path) -> Result<Handle, String> {
+    match imaging::load_oriented(path) {
+        Ok(img) => {
+            let (w, h) = img.dimensions();
+            Ok(Handle::from_pixels(w, h, img.to_bgra8().into_raw()))
+        }
+        Err(err) => {
+            ieprintln!("Error loading " path;? ": " error_chain(&err) ".");
+            Err(ifmt!("Error loading file: " error_chain(&err)))
+        }
     }
 }
@@ -1,14 +1,104 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::Result;
 use chrono::naive::{NaiveDate, NaiveDateTime};
-use exif::{DateTime as ExifDateTime, Exif, Field, In, Tag, Value};
+use exif::{DateTime as ExifDateTime, Exif, Field, In, Reader as ExifReader, Tag, Value};
+use image::imageops::FilterType;
+use image::io::Reader as ImageReader;
+use image::jpeg::JpegDecoder;
+use image::{DynamicImage, ImageDecoder};
+
+use crate::dates::Timestamp;
+use crate::model::{CameraInfo, GpsPosition};
 
 pub trait ExifExt {
+    /// Date & time from `tag`, with sub-second part and UTC offset from the corresponding
+    /// `SubSecTime*` and `OffsetTime*` tags, if present.
     fn datetime(&self, tag: Tag) -> Option<ExifDateTime>;
 
-    // TODO[LATER]: use some orientation enum / stricter type instead of raw u16
-    // for meaning, see: https://magnushoff.com/articles/jpeg-orientation/
+    /// Date & time in UTC, as received from GPS satellites.
+    fn gps_datetime(&self) -> Option<NaiveDateTime>;
+
+    /// Position in decimal degrees, from `GPSLatitude`/`GPSLongitude` rationals and their refs.
+    fn gps_position(&self) -> Option<GpsPosition>;
+
+    /// Camera, lens and exposure settings - empty if not recorded.
+    fn camera(&self) -> CameraInfo;
+
     // TODO[LATER]: test exif deorienting with cases from: https://github.com/recurser/exif-orientation-examples
     // (see also: https://www.daveperrett.com/articles/2012/07/28/exif-orientation-handling-is-a-ghetto)
-    fn orientation(&self) -> Option<u16>;
+    fn orientation(&self) -> Option<Orientation>;
+}
+
+/// Exif orientation of an image, describing how the stored pixels must be transformed for
+/// display. For meaning, see: https://magnushoff.com/articles/jpeg-orientation/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    Normal = 1,
+    FlipHorizontal = 2,
+    Rotate180 = 3,
+    FlipVertical = 4,
+    Transpose = 5,
+    Rotate90 = 6,
+    Transverse = 7,
+    Rotate270 = 8,
+}
+
+impl Orientation {
+    pub fn from_exif(value: u16) -> Option<Self> {
+        use Orientation::*;
+        Some(match value {
+            1 => Normal,
+            2 => FlipHorizontal,
+            3 => Rotate180,
+            4 => FlipVertical,
+            5 => Transpose,
+            6 => Rotate90,
+            7 => Transverse,
+            8 => Rotate270,
+            _ => return None,
+        })
+    }
+
+    /// Transform the stored image so that it's displayed upright.
+    pub fn apply(self, img: DynamicImage) -> DynamicImage {
+        use Orientation::*;
+        match self {
+            Normal => img,
+            FlipHorizontal => img.fliph(),
+            Rotate180 => img.rotate180(),
+            FlipVertical => img.flipv(),
+            Transpose => img.rotate90().fliph(),
+            Rotate90 => img.rotate90(),
+            Transverse => img.rotate270().fliph(),
+            Rotate270 => img.rotate270(),
+        }
+    }
+}
+
+/// Read and decode image from file at `path`, transformed upright according to its Exif
+/// orientation.
+pub fn load_oriented(path: &Path) -> Result<DynamicImage> {
+    let buf = fs::read(path)?;
+    let img = ImageReader::new(io::Cursor::new(&buf))
+        .with_guessed_format()?
+        .decode()?;
+    let orientation = ExifReader::new()
+        .read_from_container(&mut io::Cursor::new(&buf))
+        .ok()
+        .and_then(|exif| exif.orientation());
+    Ok(match orientation {
+        Some(orientation) => orientation.apply(img),
+        None => img,
+    })
+}
+
+/// Width and height of JPEG image in `buf`, read from its header without decoding the pixels.
+pub fn jpeg_dimensions(buf: &[u8]) -> Option<(u32, u32)> {
+    let decoder = JpegDecoder::new(io::Cursor::new(buf)).ok()?;
+    Some(decoder.dimensions())
 }
 
 /// Macro making retrieval of Exif fields less visually cluttered.
@@ -30,30 +120,350 @@ macro_rules! exif_field {
 
 impl ExifExt for Exif {
     fn datetime(&self, tag: Tag) -> Option<ExifDateTime> {
-        exif_field! {
+        let mut datetime = exif_field! {
             self[tag] as Value::Ascii{ref v} => {
                 ExifDateTime::from_ascii(v).ok()
             }
+        }?;
+        let (subsec, offset) = match tag {
+            Tag::DateTime => (Tag::SubSecTime, Tag::OffsetTime),
+            Tag::DateTimeOriginal => (Tag::SubSecTimeOriginal, Tag::OffsetTimeOriginal),
+            Tag::DateTimeDigitized => (Tag::SubSecTimeDigitized, Tag::OffsetTimeDigitized),
+            _ => return Some(datetime),
+        };
+        // Malformed optional parts are ignored - they're not worth losing the date for.
+        let _: Option<()> = exif_field! {
+            self[subsec] as Value::Ascii{ref v} => {
+                datetime.parse_subsec(v).ok()
+            }
+        };
+        let _: Option<()> = exif_field! {
+            self[offset] as Value::Ascii{ref v} => {
+                datetime.parse_offset(v).ok()
+            }
+        };
+        Some(datetime)
+    }
+
+    fn gps_datetime(&self) -> Option<NaiveDateTime> {
+        let date = exif_field! {
+            self[Tag::GPSDateStamp] as Value::Ascii{ref v} => {
+                let v = std::str::from_utf8(v).ok()?;
+                NaiveDate::parse_from_str(v, "%Y:%m:%d").ok()
+            }
+        }?;
+        let time = match self.get_field(Tag::GPSTimeStamp, In::PRIMARY) {
+            Some(Field {
+                value: Value::Rational(ref hms),
+                ..
+            }) if hms.len() == 3 && hms.iter().all(|r| r.denom != 0) => {
+                let secs = hms[0].to_f64() * 3600. + hms[1].to_f64() * 60. + hms[2].to_f64();
+                date.and_hms(0, 0, 0)
+                    + chrono::Duration::milliseconds((secs * 1000.).round() as i64)
+            }
+            _ => return None,
+        };
+        Some(time)
+    }
+
+    fn gps_position(&self) -> Option<GpsPosition> {
+        // Coordinates are stored as degrees, minutes and seconds; the hemisphere is in another tag.
+        let degrees = |tag, ref_tag, negative: u8| {
+            let value = match self.get_field(tag, In::PRIMARY) {
+                Some(Field {
+                    value: Value::Rational(ref dms),
+                    ..
+                }) if dms.len() == 3 && dms.iter().all(|r| r.denom != 0) => {
+                    dms[0].to_f64() + dms[1].to_f64() / 60. + dms[2].to_f64() / 3600.
+                }
+                _ => return None,
+            };
+            let hemisphere = exif_field! {
+                self[ref_tag] as Value::Ascii{ref v} => {
+                    v.first().copied()
+                }
+            };
+            Some(match hemisphere {
+                Some(h) if h.to_ascii_uppercase() == negative => -value,
+                _ => value,
+            })
+        };
+        let altitude = exif_field! {
+            self[Tag::GPSAltitude] as Value::Rational{ref v} => {
+                (v.denom != 0).then(|| v.to_f64())
+            }
+        };
+        // Reference 1 means below sea level.
+        let below = exif_field! {
+            self[Tag::GPSAltitudeRef] as Value::Byte{ref v} => {
+                Some(*v == 1)
+            }
+        };
+        Some(GpsPosition {
+            latitude: degrees(Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S')?,
+            longitude: degrees(Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W')?,
+            altitude: altitude.map(|a| if below == Some(true) { -a } else { a }),
+        })
+    }
+
+    fn camera(&self) -> CameraInfo {
+        // Strings are often padded with spaces or NULs to a fixed length.
+        let ascii = |tag| {
+            exif_field! {
+                self[tag] as Value::Ascii{ref v} => {
+                    let s = String::from_utf8_lossy(v);
+                    let s = s.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+                    (!s.is_empty()).then(|| s.to_string())
+                }
+            }
+        };
+        let rational = |tag| {
+            exif_field! {
+                self[tag] as Value::Rational{ref v} => {
+                    (v.denom != 0).then(|| v.to_f64())
+                }
+            }
+        };
+        CameraInfo {
+            make: ascii(Tag::Make),
+            model: ascii(Tag::Model),
+            lens: ascii(Tag::LensModel),
+            focal_length: rational(Tag::FocalLength),
+            exposure: rational(Tag::ExposureTime),
+            iso: exif_field! {
+                self[Tag::PhotographicSensitivity] as Value::Short{ref v} => {
+                    Some(u32::from(*v))
+                }
+            },
         }
     }
 
-    fn orientation(&self) -> Option<u16> {
+    fn orientation(&self) -> Option<Orientation> {
         exif_field! {
             self[Tag::Orientation] as Value::Short{ref v} => {
-                Some(*v)
+                Orientation::from_exif(*v)
             }
         }
     }
 }
 
+/// Perceptual "difference hash" of an image: similar images (e.g. resized or recompressed copies)
+/// get hashes differing in only a few bits. See:
+/// https://www.hackerfactor.com/blog/index.php?/archives/529-Kind-of-Like-That.html
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0];
+            hash = hash << 1 | bit as u64;
+        }
+    }
+    hash
+}
+
 pub trait ExifDateTimeExt {
+    /// Wall-clock time, including sub-second part if known.
     fn to_naive_opt(&self) -> Option<NaiveDateTime>;
+
+    fn to_timestamp_opt(&self) -> Option<Timestamp>;
 }
 
 impl ExifDateTimeExt for ExifDateTime {
     fn to_naive_opt(&self) -> Option<NaiveDateTime> {
         NaiveDate::from_ymd_opt(self.year.into(), self.month.into(), self.day.into()).and_then(
-            |date| date.and_hms_opt(self.hour.into(), self.minute.into(), self.second.into()),
+            |date| {
+                date.and_hms_nano_opt(
+                    self.hour.into(),
+                    self.minute.into(),
+                    self.second.into(),
+                    self.nanosecond.unwrap_or(0),
+                )
+            },
         )
     }
+
+    fn to_timestamp_opt(&self) -> Option<Timestamp> {
+        Some(Timestamp {
+            local: self.to_naive_opt()?,
+            offset: self.offset.map(i32::from),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{GenericImageView, Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn dhash_similar_images() {
+        let gradient = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 48, |x, y| {
+            let v = ((x * 3 + y * 2) % 256) as u8;
+            Rgb([v, v / 2, 255 - v])
+        }));
+        let resized = gradient.resize_exact(32, 24, FilterType::CatmullRom);
+        let mut recompressed = Vec::new();
+        gradient
+            .write_to(&mut recompressed, image::ImageOutputFormat::Jpeg(30))
+            .unwrap();
+        let recompressed = image::load_from_memory(&recompressed).unwrap();
+        let other = gradient.fliph();
+
+        let distance = |a: &DynamicImage, b: &DynamicImage| (dhash(a) ^ dhash(b)).count_ones();
+        assert!(distance(&gradient, &resized) <= 4);
+        assert!(distance(&gradient, &recompressed) <= 4);
+        assert!(distance(&gradient, &other) > 16);
+    }
+
+    /// Build a 2x3 image, with each pixel having a distinct value.
+    fn sample() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(2, 3, |x, y| {
+            Rgb([(x + 2 * y) as u8, 0, 0])
+        }))
+    }
+
+    /// Red channel of all pixels, row by row.
+    fn pixels(img: &DynamicImage) -> Vec<Vec<u8>> {
+        (0..img.height())
+            .map(|y| (0..img.width()).map(|x| img.get_pixel(x, y)[0]).collect())
+            .collect()
+    }
+
+    #[test]
+    fn orientation_transforms() {
+        use Orientation::*;
+        let cases = [
+            (Normal, vec![vec![0, 1], vec![2, 3], vec![4, 5]]),
+            (FlipHorizontal, vec![vec![1, 0], vec![3, 2], vec![5, 4]]),
+            (Rotate180, vec![vec![5, 4], vec![3, 2], vec![1, 0]]),
+            (FlipVertical, vec![vec![4, 5], vec![2, 3], vec![0, 1]]),
+            (Transpose, vec![vec![0, 2, 4], vec![1, 3, 5]]),
+            (Rotate90, vec![vec![4, 2, 0], vec![5, 3, 1]]),
+            (Transverse, vec![vec![5, 3, 1], vec![4, 2, 0]]),
+            (Rotate270, vec![vec![1, 3, 5], vec![0, 2, 4]]),
+        ];
+        for (orientation, want) in cases {
+            assert_eq!(
+                pixels(&orientation.apply(sample())),
+                want,
+                "orientation: {:?}",
+                orientation
+            );
+            assert_eq!(
+                Orientation::from_exif(orientation as u16),
+                Some(orientation)
+            );
+        }
+        assert_eq!(Orientation::from_exif(0), None);
+        assert_eq!(Orientation::from_exif(9), None);
+    }
+
+    /// Exif block with given fields in the primary IFD.
+    fn exif_with<const N: usize>(fields: [(Tag, Value); N]) -> Exif {
+        let fields = fields.map(|(tag, value)| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value,
+        });
+        let mut writer = exif::experimental::Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut buf = io::Cursor::new(Vec::new());
+        writer.write(&mut buf, false).unwrap();
+        ExifReader::new().read_raw(buf.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn exif_datetime_with_subsec_offset_and_gps() {
+        use exif::Rational;
+
+        let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+        let rational = |num, denom| Rational { num, denom };
+        let fields = [
+            (Tag::DateTimeOriginal, ascii("2019:07:01 14:00:03")),
+            (Tag::SubSecTimeOriginal, ascii("25")),
+            (Tag::OffsetTimeOriginal, ascii("+02:00")),
+            (Tag::DateTime, ascii("2019:07:01 14:00:03")),
+            (Tag::GPSDateStamp, ascii("2019:07:01")),
+            (
+                Tag::GPSTimeStamp,
+                Value::Rational(vec![rational(12, 1), rational(1, 1), rational(305, 10)]),
+            ),
+        ];
+        let exif = exif_with(fields);
+
+        let at = |h, m, s, ms| NaiveDate::from_ymd(2019, 7, 1).and_hms_milli(h, m, s, ms);
+        let timestamp = |tag| exif.datetime(tag).and_then(|d| d.to_timestamp_opt());
+        assert_eq!(
+            timestamp(Tag::DateTimeOriginal),
+            Some(Timestamp {
+                local: at(14, 0, 3, 250),
+                offset: Some(120),
+            })
+        );
+        assert_eq!(
+            timestamp(Tag::DateTime),
+            Some(Timestamp::naive(at(14, 0, 3, 0)))
+        );
+        assert_eq!(exif.gps_datetime(), Some(at(12, 1, 30, 500)));
+    }
+
+    #[test]
+    fn exif_gps_position() {
+        use exif::Rational;
+
+        let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+        let dms = |d, m, s| {
+            Value::Rational(vec![
+                Rational { num: d, denom: 1 },
+                Rational { num: m, denom: 1 },
+                Rational { num: s, denom: 100 },
+            ])
+        };
+        let fields = [
+            (Tag::GPSLatitudeRef, ascii("S")),
+            (Tag::GPSLatitude, dms(33, 51, 3600)),
+            (Tag::GPSLongitudeRef, ascii("E")),
+            (Tag::GPSLongitude, dms(151, 12, 0)),
+            (Tag::GPSAltitudeRef, Value::Byte(vec![1])),
+            (
+                Tag::GPSAltitude,
+                Value::Rational(vec![Rational { num: 25, denom: 2 }]),
+            ),
+        ];
+        let exif = exif_with(fields);
+
+        let gps = exif.gps_position().unwrap();
+        assert!((gps.latitude - -33.86).abs() < 1e-9, "{:?}", gps);
+        assert!((gps.longitude - 151.2).abs() < 1e-9, "{:?}", gps);
+        assert_eq!(gps.altitude, Some(-12.5));
+    }
+
+    #[test]
+    fn exif_camera_info() {
+        let exif = exif_with([
+            (Tag::Make, Value::Ascii(vec![b"Canon\0\0".to_vec()])),
+            (Tag::Model, Value::Ascii(vec![b"Canon EOS 400D  ".to_vec()])),
+            (
+                Tag::ExposureTime,
+                Value::Rational(vec![exif::Rational { num: 1, denom: 250 }]),
+            ),
+            (Tag::PhotographicSensitivity, Value::Short(vec![400])),
+        ]);
+        let camera = exif.camera();
+        assert_eq!(
+            camera,
+            CameraInfo {
+                make: Some("Canon".to_string()),
+                model: Some("Canon EOS 400D".to_string()),
+                exposure: Some(0.004),
+                iso: Some(400),
+                ..Default::default()
+            }
+        );
+        assert_eq!(camera.name().as_deref(), Some("Canon EOS 400D"));
+    }
 }
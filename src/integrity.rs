@@ -0,0 +1,128 @@
+//! Resolving integrity incidents, i.e. files found with contents not matching their hash in DB.
+
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+
+use crate::db::{self, SyncedDb};
+use crate::interlude::*;
+use crate::model::{FileStat, Incident};
+use crate::replicate::write_verified;
+use crate::scanning::{find_file, hash_matches, Tree};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resolution {
+    /// The new contents are fine (e.g. the file was edited on purpose) - the file will be
+    /// re-scanned with them during next scan.
+    Accept,
+    /// Overwrite the file with a copy having the expected hash, found on another marker.
+    Restore,
+}
+
+/// Resolve open incident `id`. Restoring needs both the incident's marker, and another marker
+/// holding the expected contents, to be among connected `trees`.
+pub fn resolve(db: &SyncedDb, trees: &[Tree], id: i64, resolution: Resolution) -> Result<()> {
+    let incident = match db::open_incident(&db.lock().unwrap(), id)? {
+        Some(incident) => incident,
+        None => bail!("no open incident #{}", id),
+    };
+    match resolution {
+        Resolution::Accept => {
+            let db = db.lock().unwrap();
+            db::clear_location_stat(&db, &incident.marker, &incident.path)?;
+            db::resolve_incident(&db, id, "accepted")?;
+        }
+        Resolution::Restore => {
+            let stat = restore(db, trees, &incident)?;
+            let db = db.lock().unwrap();
+            db::set_location_stat(&db, &incident.marker, &incident.path, &stat)?;
+            db::resolve_incident(&db, id, "restored")?;
+        }
+    }
+    Ok(())
+}
+
+fn restore(db: &SyncedDb, trees: &[Tree], incident: &Incident) -> Result<FileStat> {
+    let target = trees
+        .iter()
+        .find(|tree| tree.marker == incident.marker)
+        .with_context(|| ifmt!("marker " incident.marker;? " is not connected"))?;
+    let dest = target.path_of(&incident.path);
+
+    let locations = db::locations_of(&db.lock().unwrap(), &incident.expected_hash)?;
+    let sources = locations
+        .iter()
+        .filter(|(marker, path)| (marker, path) != (&incident.marker, &incident.path))
+        .filter_map(|location| find_file(trees, std::slice::from_ref(location)))
+        .map(|(_, path)| path);
+    // Copies found on disk may have gone bad as well, so try all of them until a good one is found.
+    for source in sources {
+        let buf = fs::read(&source).with_context(|| ifmt!("reading " source;?))?;
+        if !hash_matches(&buf, &incident.expected_hash) {
+            ieprintln!("Skipping " source;? ": contents don't match hash " incident.expected_hash);
+            continue;
+        }
+        iprintln!("Restoring " dest;? " from " source;?);
+        write_verified(&dest, &buf, &incident.expected_hash)?;
+        let stat = fs::metadata(&dest)
+            .and_then(|m| FileStat::of(&m))
+            .with_context(|| ifmt!("reading metadata of " dest;?))?;
+        return Ok(stat);
+    }
+    bail!(
+        "no copy of {} found on connected markers",
+        incident.expected_hash
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use crate::model::FileInfo;
+    use crate::scanning::{hash, stage2, tree_at};
+
+    use super::*;
+
+    #[test]
+    fn detect_and_restore_corrupted_file() {
+        // arrange
+
+        let (root_a, root_b) = (tempdir().unwrap(), tempdir().unwrap());
+        let tree_a = tree_at(root_a.path(), "marker-a");
+        let tree_b = tree_at(root_b.path(), "marker-b");
+        let contents = b"fake jpeg contents";
+        fs::write(root_a.path().join("foo.jpg"), contents).unwrap();
+        fs::write(root_b.path().join("bar.jpg"), contents).unwrap();
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let info = FileInfo {
+            hash: hash(contents),
+            ..Default::default()
+        };
+        db::upsert(&conn, "marker-a", "foo.jpg", &info).unwrap();
+        db::upsert(&conn, "marker-b", "bar.jpg", &info).unwrap();
+        let db = Arc::new(Mutex::new(conn));
+
+        fs::write(root_a.path().join("foo.jpg"), b"fake jpeg c0ntents").unwrap();
+
+        // act & assert
+
+        stage2(&tree_a, &db, true).unwrap();
+        let incidents = db::open_incidents(&db.lock().unwrap()).unwrap();
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(
+            (
+                incidents[0].path.as_str(),
+                incidents[0].expected_hash.as_str()
+            ),
+            ("foo.jpg", info.hash.as_str())
+        );
+
+        let trees = [tree_a, tree_b];
+        resolve(&db, &trees, incidents[0].id, Resolution::Restore).unwrap();
+        assert_eq!(fs::read(root_a.path().join("foo.jpg")).unwrap(), contents);
+        assert_eq!(db::open_incidents(&db.lock().unwrap()).unwrap(), vec![]);
+    }
+}
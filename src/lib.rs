@@ -1,10 +1,20 @@
 pub mod config;
+pub mod coverage;
+pub mod datepaths;
+pub mod dates;
 pub mod db;
+pub mod duplicates;
+pub mod geocode;
 pub mod gui;
 pub mod imaging;
+pub mod integrity;
 pub mod interlude;
 pub mod model;
 pub mod pathwalk;
+pub mod query;
+pub mod replicate;
 pub mod res;
 pub mod scanning;
+pub mod thumbnails;
+pub mod video;
 pub mod widgets;
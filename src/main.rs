@@ -1,65 +1,326 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::thread;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use clap::{ArgGroup, Parser, Subcommand};
 use iced::pure::Application;
+use regex::Regex;
 
-use backer::config;
-use backer::db;
+use backer::config::{self, Config};
+use backer::coverage;
+use backer::datepaths;
+use backer::dates;
+use backer::db::{self, SyncedDb};
+use backer::geocode;
 use backer::gui::Gui;
+use backer::integrity::{self, Resolution};
 use backer::interlude::*;
+use backer::replicate::{self, replicate};
 use backer::scanning::*;
 
 // TODO: migrate to iced v0.4.0 with its new features & architecture
 // TODO[LATER]: load marker_paths from JSON
-// TODO: load date-from-path regexps from JSON:
-//   {"paths-to-dates": {"sf7-c-fotki": {".*(20\d\d)-(\d\d)-(\d\d)": "\1-\2-\3"}}}
-// TODO: introduce "dry-run" mode for testing date-from-path regexps
-// - [LATER] show which rule index detected date in which file (also Exif)
-// - [LATER] also show files that were still "missed" by detection
-// TODO: use date-from-path regexps
-// TODO: skip small images (threshold size configurable in JSON - dimensions or bytes?)
-// - or maybe just skip them in viewer for now?
-// TODO: re-scan disks and re-generate data in DB
-// - for above step, we should probably have an iterator easily generating file paths from marker
-//   path (similar as already exists for initial scan, but extracted for reuse)
 // TODO: delete files not found on disk from DB (only locations - keep "files")
 // - for above step, we need to be able to easily check if specific path exists at location in
 //   specific marker's tree
 //   - it should use same filters as the main files iterator (incl. extension, jpeg size)
-// TODO: merge 'view' and 'main' binaries
-// TODO: deorient thumbnails stored into DB
 
-fn main() {
-    if let Err(err) = run() {
-        ieprintln!("Error: " error_chain(&err) ".");
+#[derive(Parser)]
+#[clap(
+    version,
+    about = "Keeps track of photo backups spread over multiple disks"
+)]
+struct Cli {
+    /// Path of the config file.
+    #[clap(long, global = true, default_value = "backer.toml")]
+    config: PathBuf,
+    /// Path of the database file.
+    #[clap(long, global = true, default_value = "backer.db")]
+    db: PathBuf,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show the gallery, while scanning connected disks in background (default)
+    Gui {
+        /// Don't scan disks in background
+        #[clap(long)]
+        no_scan: bool,
+    },
+    /// Scan connected disks and update the database
+    Scan {
+        /// Re-read all files and verify them against the database, even if they look unchanged
+        #[clap(long)]
+        deep: bool,
+    },
+    /// Check if files known in the database are still present & unchanged on connected disks
+    Verify {
+        /// Only verify the disk with this marker
+        #[clap(long)]
+        marker: Option<String>,
+    },
+    /// List files found with contents not matching their hash in the database
+    Incidents,
+    /// Resolve an integrity incident listed by `incidents`
+    #[clap(group(ArgGroup::new("resolution").required(true).args(&["accept", "restore"])))]
+    Resolve {
+        /// Number of the incident
+        id: i64,
+        /// Keep the new contents of the file
+        #[clap(long)]
+        accept: bool,
+        /// Overwrite the file with a copy having the expected contents, found on another connected marker
+        #[clap(long)]
+        restore: bool,
+    },
+    /// List files whose dates found at different locations or in different sources disagree
+    DateConflicts,
+    /// Show which dates would be detected for files on connected disks, and by which rules
+    Datepaths {
+        /// Print the report as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Name places where photos were taken, using the `places-file` from config
+    Places {
+        /// Name again also files which already have a place
+        #[clap(long)]
+        refresh: bool,
+    },
+    /// Print a sample config, then the parsed contents of the config file
+    Config,
+    /// List files present on too few distinct markers (exit code 3 if any are found)
+    Coverage {
+        #[clap(long, default_value_t = 2)]
+        min_markers: u32,
+        /// Print the report as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Copy files present on too few distinct markers onto another connected marker
+    Replicate {
+        /// Marker to copy the files onto
+        #[clap(long)]
+        to: String,
+        #[clap(long, default_value_t = 2)]
+        min_markers: u32,
+        /// Only show what would be copied
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+/// Exit code of `coverage` command, when files backed up insufficiently were found.
+const EXIT_NOT_COVERED: u8 = 3;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(code) => code,
+        Err(err) => {
+            ieprintln!("Error: " error_chain(&err) ".");
+            ExitCode::FAILURE
+        }
     }
 }
 
-fn run() -> Result<()> {
+fn run(cli: Cli) -> Result<ExitCode> {
     // TODO[LATER]: run rustfmt on this repo
     // TODO[LATER]: run clippy on this repo
-    println!("Hello, world!");
 
-    let db = db::open("backer.db")?;
+    let command = cli.command.unwrap_or(Command::Gui { no_scan: false });
+    if let Command::Config = command {
+        return print_config(&cli.config).map(|_| ExitCode::SUCCESS);
+    }
 
     // Read and parse config.
-    let config = config::read("backer.toml")?;
+    let config = config::read(&cli.config)?;
 
-    let scanner = {
+    if let Command::Datepaths { json } = command {
+        return datepaths(config, json).map(|_| ExitCode::SUCCESS);
+    }
+
+    let db = db::open(&cli.db)?;
+
+    match command {
+        Command::Gui { no_scan } => gui(db, config, !no_scan)?,
+        Command::Scan { deep } => scan(db, config, deep)?,
+        Command::Verify { marker } => verify(db, config, marker)?,
+        Command::Incidents => {
+            let incidents = db::open_incidents(&db.lock().unwrap())?;
+            for i in &incidents {
+                let stat = if i.stat_changed {
+                    "size/mtime changed"
+                } else {
+                    "size/mtime unchanged"
+                };
+                iprintln!("#" i.id " " i.detected " " i.marker ": " i.path);
+                iprintln!("    expected " i.expected_hash ", found " i.actual_hash " (" stat ")");
+            }
+            iprintln!(incidents.len() " open incident(s).");
+        }
+        Command::Resolve { id, accept, .. } => {
+            let resolution = if accept {
+                Resolution::Accept
+            } else {
+                Resolution::Restore
+            };
+            integrity::resolve(&db, &connected_trees(&config), id, resolution)?;
+        }
+        Command::DateConflicts => {
+            let db = db.lock().unwrap();
+            let files = db::files_tagged(&db, dates::CONFLICT_TAG)?;
+            for (hash, date) in &files {
+                let date = date.map_or("unknown date".to_string(), |d| d.to_string());
+                iprintln!(hash " - " date);
+                for ((marker, path), (source, date)) in db::date_candidates_of(&db, hash)? {
+                    iprintln!("    " date " " source.name() " at " marker ": " path);
+                }
+            }
+            iprintln!(files.len() " file(s) with conflicting dates.");
+        }
+        Command::Places { refresh } => {
+            let path = config
+                .places_file
+                .as_ref()
+                .context("no places-file in config")?;
+            let places = geocode::Places::read(path)?;
+            let named = geocode::assign_places(&db.lock().unwrap(), &places, refresh)?;
+            iprintln!("Named places of " named " file(s).");
+        }
+        Command::Coverage { min_markers, json } => {
+            let report = coverage::report(&db.lock().unwrap(), min_markers)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", report);
+            }
+            if report.files > 0 {
+                return Ok(ExitCode::from(EXIT_NOT_COVERED));
+            }
+        }
+        Command::Replicate {
+            to,
+            min_markers,
+            dry_run,
+        } => {
+            let trees = connected_trees(&config);
+            let target = trees
+                .iter()
+                .find(|tree| tree.marker == to)
+                .with_context(|| ifmt!("marker " to;? " is not connected"))?;
+            let template = config
+                .replicate_path
+                .get(&to)
+                .map(String::as_str)
+                .unwrap_or(replicate::DEFAULT_TEMPLATE);
+            let stats = replicate(&db, &trees, target, template, min_markers, dry_run)?;
+            iprintln!(
+                "Copied " stats.copied " file(s), " coverage::human_bytes(stats.bytes) "; "
+                "found " stats.found " already present; skipped " stats.skipped "."
+            );
+        }
+        Command::Config | Command::Datepaths { .. } => unreachable!(),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn gui(db: SyncedDb, config: Config, with_scan: bool) -> Result<()> {
+    let scanner = with_scan.then(|| {
         // TODO[LATER]: consider not cloning config maybe (?)
         // TODO[LATER]: somehow pass args prettier to the thread
-        let (db, config) = (db.clone(), config);
-        thread::spawn(move || scan(db, config).unwrap())
-    };
+        let (db, config) = (db.clone(), config.clone());
+        thread::spawn(move || scan(db, config, false))
+    });
 
     // TODO[LATER]: see if IPFS can be reused from: https://github.com/FuzzrNet/Fuzzr
 
-    Gui::run(iced::Settings::with_flags(db))?;
+    Gui::run(iced::Settings::with_flags((db, config)))?;
 
     // TODO: somehow be checking status of the thread before GUI finishes; and/or run the thread in loop?
-    scanner
-        .join()
-        .map_err(|err| anyhow!(ifmt!("error scanning: " err;?)))?;
+    if let Some(scanner) = scanner {
+        scanner
+            .join()
+            .map_err(|err| anyhow!(ifmt!("error scanning: " err;?)))??;
+    }
 
     Ok(())
 }
+
+fn verify(db: SyncedDb, config: Config, marker: Option<String>) -> Result<()> {
+    let trees = connected_trees(&config);
+    if let Some(marker) = &marker {
+        if !trees.iter().any(|tree| &tree.marker == marker) {
+            bail!("marker {:?} is not connected", marker);
+        }
+    }
+    for tree in trees {
+        if marker.is_none() || marker.as_ref() == Some(&tree.marker) {
+            iprintln!("Verifying marker " &tree.marker " at: " tree.root;?);
+            stage2(&tree, &db, true)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_config(path: &PathBuf) -> Result<()> {
+    iprintln!("SAMPLE:\n" toml::to_string(&Config {
+        image_extensions: config::default_image_extensions(),
+        video_extensions: config::default_video_extensions(),
+        ignore_small: Some(config::MinSize { w: 1024, h: 1024 }),
+        date_priority: dates::default_priority(),
+        date_conflict_hours: config::default_date_conflict_hours(),
+        fs_date_fallback: true,
+        places_file: Some(PathBuf::from("cities1000.txt")),
+        ignore_small_per_marker: HashMap::from([(
+            "marker-x".to_string(),
+            config::MinSize { w: 0, h: 0 },
+        )]),
+        fs_date_fallback_per_marker: HashMap::from([("marker-x".to_string(), false)]),
+        markers: config::Markers{
+            disk: Vec::new(),
+        },
+        date_path: HashMap::from([
+            ("marker-x".to_string(), vec![
+                config::DatePath {
+                    path: Regex::new(r"/(20\d\d)(\d\d)(\d\d)_(\d\d)(\d\d)(\d\d).jpg").unwrap(),
+                    date: "$1-$2-$3 $4:$5:$6".to_string(),
+                },
+            ]),
+        ]),
+        replicate_path: HashMap::from([
+            ("marker-x".to_string(), "{year}/{month}/{name}".to_string()),
+        ]),
+    })? "\n");
+
+    let config = config::read(path)?;
+    iprintln!("CONFIG: " config;?);
+    Ok(())
+}
+
+fn datepaths(config: Config, json: bool) -> Result<()> {
+    let mut reports = Vec::new();
+    for marker_path in &config.markers.disk {
+        let tree = match Tree::open(marker_path, &config.date_path) {
+            Ok(t) => t,
+            Err(e) => {
+                ieprintln!("Skipping " marker_path;? ": " e);
+                continue;
+            }
+        };
+        let report = datepaths::report(&tree, &config)?;
+        if !json {
+            print!("{}", report);
+        }
+        reports.push(report);
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    }
+    Ok(())
+}
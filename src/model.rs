@@ -1,8 +1,115 @@
+use std::fs::Metadata;
+use std::io;
+use std::time::UNIX_EPOCH;
+
 use chrono::naive::NaiveDateTime;
 
-#[derive(Debug, PartialEq)]
+/// Version of the algorithm generating thumbnails and other data derived from decoded images.
+/// Bump it to have them regenerated for all files during next scan.
+pub const THUMBNAIL_VERSION: u32 = 2;
+
+#[derive(Debug, Default, PartialEq)]
 pub struct FileInfo {
     pub hash: String,
     pub date: Option<NaiveDateTime>,
     pub thumb: Vec<u8>,
+    /// Size of file contents in bytes (unknown for files scanned by old versions of backer).
+    pub size: Option<u64>,
+    /// Duration of a video file; None for images, and videos with unreadable headers.
+    pub duration_ms: Option<u64>,
+    pub video: bool,
+    /// Perceptual hash (see `imaging::dhash`), if the file could be decoded as an image.
+    pub phash: Option<u64>,
+    /// Dimensions of the image as displayed, i.e. after applying Exif orientation.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Where the photo was taken, according to its Exif data.
+    pub gps: Option<GpsPosition>,
+    pub camera: CameraInfo,
+}
+
+/// Camera settings of a photo, from its Exif data.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CameraInfo {
+    /// Manufacturer of the camera, e.g. "Canon".
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+    /// Focal length in millimeters.
+    pub focal_length: Option<f64>,
+    /// Exposure time in seconds.
+    pub exposure: Option<f64>,
+    pub iso: Option<u32>,
+}
+
+impl CameraInfo {
+    /// Make and model, without repeating the make if the model already starts with it (as in
+    /// "Canon" + "Canon EOS 5D").
+    pub fn name(&self) -> Option<String> {
+        match (&self.make, &self.model) {
+            (Some(make), Some(model)) if model.starts_with(make.as_str()) => Some(model.clone()),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (make, model) => make.clone().or_else(|| model.clone()),
+        }
+    }
+}
+
+/// Everything known about a file, as shown in the info panel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileDetails {
+    pub hash: String,
+    pub date: Option<NaiveDateTime>,
+    pub size: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub camera: CameraInfo,
+    /// Name of the nearest place (see `geocode::Places`), if the file has a GPS position.
+    pub place: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpsPosition {
+    /// Degrees north (negative for south).
+    pub latitude: f64,
+    /// Degrees east (negative for west).
+    pub longitude: f64,
+    /// Meters above sea level.
+    pub altitude: Option<f64>,
+}
+
+/// Size and modification time of a file at a specific location, used to detect files which
+/// didn't change since last scan.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileStat {
+    pub size: u64,
+    /// Modification time, in nanoseconds since Unix epoch.
+    pub mtime: i64,
+}
+
+impl FileStat {
+    pub fn of(metadata: &Metadata) -> io::Result<Self> {
+        let mtime = match metadata.modified()?.duration_since(UNIX_EPOCH) {
+            Ok(after) => after.as_nanos() as i64,
+            Err(before) => -(before.duration().as_nanos() as i64),
+        };
+        Ok(Self {
+            size: metadata.len(),
+            mtime,
+        })
+    }
+}
+
+/// A file found with contents not matching its hash in DB (e.g. bit rot, or overwritten by
+/// mistake).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Incident {
+    pub id: i64,
+    pub marker: String,
+    pub path: String,
+    pub expected_hash: String,
+    pub actual_hash: String,
+    pub detected: NaiveDateTime,
+    /// Whether file's size or modification time differed from the ones recorded during last scan.
+    pub stat_changed: bool,
 }
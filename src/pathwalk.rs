@@ -16,7 +16,9 @@ pub mod matcher {
     pub struct CaseInsensitiveExtensions(Vec<OsString>);
 
     impl CaseInsensitiveExtensions {
-        pub fn boxed(extensions: impl IntoIterator<Item = &'static str>) -> Box<dyn Matcher> {
+        pub fn boxed(
+            extensions: impl IntoIterator<Item = impl Into<OsString>>,
+        ) -> Box<dyn Matcher> {
             Box::new(Self(Vec::from_iter(
                 extensions.into_iter().map(|s| s.into()),
             )))
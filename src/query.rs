@@ -0,0 +1,408 @@
+//! Query language of the gallery search box, e.g.:
+//!
+//! ```text
+//! tag:holiday -tag:hidden date:2019-06..2019-08 camera:"SM-G930F" mp:8.. marker:sf7-c-fotki
+//! ```
+//!
+//! A query is a list of whitespace-separated `key:value` terms, all of which must match. Values
+//! with spaces must be quoted, and a `-` before a term negates it. Words without a key match paths
+//! containing them. Texts of `camera`, `lens`, `place` and words without a key are matched ignoring
+//! case, while `path` is a case-sensitive glob pattern (e.g. `path:*/DCIM/*`).
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDate;
+use rusqlite::types::Value;
+
+use crate::interlude::*;
+
+const KEYS: &str = "tag, date, camera, lens, place, mp, marker, path";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    pub clauses: Vec<Clause>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clause {
+    pub negated: bool,
+    pub term: Term,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    /// File carries the tag.
+    Tag(String),
+    /// File is dated between the dates (start inclusive, end exclusive), if given.
+    Date(Option<NaiveDate>, Option<NaiveDate>),
+    /// Make or model of the camera contains the text (case-insensitive).
+    Camera(String),
+    /// Name of the lens contains the text (case-insensitive).
+    Lens(String),
+    /// Name of the place contains the text (case-insensitive).
+    Place(String),
+    /// Image has between the numbers of megapixels (both inclusive), if given.
+    Megapixels(Option<f64>, Option<f64>),
+    /// File has a location on the marker.
+    Marker(String),
+    /// File has a location with path matching the glob pattern (see SQLite `GLOB`).
+    Path(String),
+    /// File has a location with path containing the text (case-insensitive).
+    Word(String),
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut chars = text.chars().peekable();
+        let mut clauses = Vec::new();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+            let negated = chars.next_if_eq(&'-').is_some();
+            let mut key = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !matches!(c, ':' | '"')) {
+                key.push(c);
+            }
+            let (key, value) = if chars.next_if_eq(&':').is_some() {
+                let value = word(&mut chars)?;
+                if value.is_empty() {
+                    bail!("missing value of {:?}", key);
+                }
+                (key, value)
+            } else if key.is_empty() {
+                // E.g. a lone `-`, or `""`.
+                let value = word(&mut chars)?;
+                if value.is_empty() {
+                    bail!("empty search term");
+                }
+                (key, value)
+            } else {
+                (String::new(), key)
+            };
+            let term = match key.as_str() {
+                "" => Term::Word(value),
+                "tag" => Term::Tag(value),
+                "date" => date_range(&value)?,
+                "camera" => Term::Camera(value),
+                "lens" => Term::Lens(value),
+                "place" => Term::Place(value),
+                "mp" => megapixels_range(&value)?,
+                "marker" => Term::Marker(value),
+                "path" => Term::Path(value),
+                _ => bail!("unknown key {:?}, expected one of: {}", key, KEYS),
+            };
+            clauses.push(Clause { negated, term });
+        }
+        Ok(Self { clauses })
+    }
+
+    /// Values of the `?` parameters in SQL conditions of the clauses, in order.
+    pub fn params(&self) -> Vec<Value> {
+        self.clauses.iter().flat_map(|c| c.term.params()).collect()
+    }
+
+    /// Tags which files must carry to match.
+    pub fn included_tags(&self) -> impl Iterator<Item = &String> {
+        self.clauses.iter().filter_map(|clause| match clause {
+            Clause {
+                negated: false,
+                term: Term::Tag(tag),
+            } => Some(tag),
+            _ => None,
+        })
+    }
+}
+
+impl Clause {
+    /// SQL condition matching rows of the `file` table.
+    pub fn sql(&self) -> String {
+        match self.negated {
+            // Conditions on missing metadata evaluate to NULL, which shouldn't exclude files.
+            true => ifmt!("NOT coalesce(" self.term.sql() ", 0)"),
+            false => ifmt!("(" self.term.sql() ")"),
+        }
+    }
+}
+
+impl Term {
+    /// SQL condition matching rows of the `file` table. Values are left as `?` parameters, to be
+    /// bound to `params()`, so that the SQL depends only on the kind of the term.
+    pub fn sql(&self) -> String {
+        let contains = |column: &str| ifmt!("instr(unicode_lower(" column "), ?) > 0");
+        match self {
+            Term::Tag(_) => "file.rowid IN (SELECT file_id FROM file_tag
+                JOIN tag ON tag.rowid = tag_id
+                WHERE tag.name = ?)"
+                .to_string(),
+            Term::Date(start, end) => {
+                let start = start.map(|_| "file.date >= ?");
+                let end = end.map(|_| "file.date < ?");
+                start
+                    .into_iter()
+                    .chain(end)
+                    .collect::<Vec<_>>()
+                    .join(" AND ")
+            }
+            Term::Camera(_) => contains("ifnull(file.make, '') || ' ' || ifnull(file.model, '')"),
+            Term::Lens(_) => contains("file.lens"),
+            Term::Place(_) => contains("file.place"),
+            Term::Megapixels(min, max) => {
+                let min = min.map(|_| "file.width * file.height >= ? * 1e6");
+                let max = max.map(|_| "file.width * file.height <= ? * 1e6");
+                min.into_iter().chain(max).collect::<Vec<_>>().join(" AND ")
+            }
+            Term::Marker(_) => "file.rowid IN (SELECT file_id FROM location
+                WHERE backend_tag = ?)"
+                .to_string(),
+            Term::Path(_) => "file.rowid IN (SELECT file_id FROM location
+                WHERE path GLOB ?)"
+                .to_string(),
+            Term::Word(_) => ifmt!(
+                "file.rowid IN (SELECT file_id FROM location
+                    WHERE " contains("path") ")"
+            ),
+        }
+    }
+
+    /// Values of the `?` parameters in `sql()`, in order.
+    pub fn params(&self) -> Vec<Value> {
+        match self {
+            Term::Tag(text) | Term::Marker(text) | Term::Path(text) => vec![text.clone().into()],
+            Term::Camera(text) | Term::Lens(text) | Term::Place(text) | Term::Word(text) => {
+                vec![text.to_lowercase().into()]
+            }
+            Term::Date(start, end) => [*start, *end]
+                .into_iter()
+                .flatten()
+                .map(|d| d.to_string().into())
+                .collect(),
+            Term::Megapixels(min, max) => [*min, *max]
+                .into_iter()
+                .flatten()
+                .map(Value::from)
+                .collect(),
+        }
+    }
+}
+
+/// Read a value, either up to the next whitespace, or in double quotes.
+fn word(chars: &mut Peekable<Chars>) -> Result<String> {
+    let mut word = String::new();
+    if chars.next_if_eq(&'"').is_some() {
+        loop {
+            match chars.next() {
+                Some('"') => return Ok(word),
+                Some(c) => word.push(c),
+                None => bail!("missing closing quote after {:?}", word),
+            }
+        }
+    }
+    while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+        word.push(c);
+    }
+    Ok(word)
+}
+
+/// Parse `date` value: a single period (`YYYY`, `YYYY-MM` or `YYYY-MM-DD`), or a range of them
+/// separated by `..`, with either end optional.
+fn date_range(value: &str) -> Result<Term> {
+    let (start, end) = match value.split_once("..") {
+        Some(("", "")) => bail!("empty date range {:?}", value),
+        Some((start, end)) => (start, end),
+        None => (value, value),
+    };
+    let start = match start {
+        "" => None,
+        start => Some(period(start)?.0),
+    };
+    let end = match end {
+        "" => None,
+        end => Some(period(end)?.1),
+    };
+    Ok(Term::Date(start, end))
+}
+
+/// Parse `mp` value: a range of numbers of megapixels separated by `..`, with either end optional.
+fn megapixels_range(value: &str) -> Result<Term> {
+    let number = |text: &str| -> Result<Option<f64>> {
+        if text.is_empty() {
+            return Ok(None);
+        }
+        match text.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Some(n)),
+            _ => bail!("invalid number of megapixels {:?}", text),
+        }
+    };
+    match value.split_once("..") {
+        Some(("", "")) | None => {
+            bail!(
+                "invalid megapixels {:?}, expected a range like 2..10, 8.. or ..2",
+                value
+            )
+        }
+        Some((min, max)) => Ok(Term::Megapixels(number(min)?, number(max)?)),
+    }
+}
+
+/// First day of the period, and first day after it.
+fn period(text: &str) -> Result<(NaiveDate, NaiveDate)> {
+    let parse = || -> Option<(NaiveDate, NaiveDate)> {
+        let parts = text
+            .split('-')
+            .map(|p| p.parse().ok())
+            .collect::<Option<Vec<u32>>>()?;
+        let ymd = |y: u32, m, d| NaiveDate::from_ymd_opt(y as i32, m, d);
+        match parts[..] {
+            [y] => Some((ymd(y, 1, 1)?, ymd(y + 1, 1, 1)?)),
+            [y, 12] => Some((ymd(y, 12, 1)?, ymd(y + 1, 1, 1)?)),
+            [y, m] => Some((ymd(y, m, 1)?, ymd(y, m + 1, 1)?)),
+            [y, m, d] => {
+                let day = ymd(y, m, d)?;
+                Some((day, day.succ_opt()?))
+            }
+            _ => None,
+        }
+    };
+    parse().with_context(|| {
+        ifmt!(
+            "invalid date " text;? ", expected YYYY, YYYY-MM or YYYY-MM-DD"
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::db;
+    use crate::model::{CameraInfo, FileInfo};
+
+    use super::*;
+
+    #[test]
+    fn parse_query() {
+        let query = Query::parse(
+            r#"tag:holiday -tag:hidden  date:2019-06..2019-12 camera:"SM G930F" signal"#,
+        )
+        .unwrap();
+        let clause = |negated, term| Clause { negated, term };
+        let ymd = |y, m, d| Some(NaiveDate::from_ymd(y, m, d));
+        assert_eq!(
+            query.clauses,
+            vec![
+                clause(false, Term::Tag("holiday".to_string())),
+                clause(true, Term::Tag("hidden".to_string())),
+                clause(false, Term::Date(ymd(2019, 6, 1), ymd(2020, 1, 1))),
+                clause(false, Term::Camera("SM G930F".to_string())),
+                clause(false, Term::Word("signal".to_string())),
+            ]
+        );
+        assert_eq!(
+            Query::parse("mp:2..10 -mp:..0.5 mp:12..").unwrap().clauses,
+            vec![
+                clause(false, Term::Megapixels(Some(2.), Some(10.))),
+                clause(true, Term::Megapixels(None, Some(0.5))),
+                clause(false, Term::Megapixels(Some(12.), None)),
+            ]
+        );
+        assert_eq!(
+            Query::parse("date:2020-02-29 -date:..2019")
+                .unwrap()
+                .clauses,
+            vec![
+                clause(false, Term::Date(ymd(2020, 2, 29), ymd(2020, 3, 1))),
+                clause(true, Term::Date(None, ymd(2020, 1, 1))),
+            ]
+        );
+        assert_eq!(Query::parse("  ").unwrap(), Query::default());
+        // Values are bound as parameters, so that statements can be reused for other values.
+        let (a, b) = (
+            Query::parse("tag:a").unwrap(),
+            Query::parse("tag:b").unwrap(),
+        );
+        assert_eq!(a.clauses[0].sql(), b.clauses[0].sql());
+        assert_eq!(a.params(), vec![Value::from("a".to_string())]);
+
+        for bad in [
+            "foo:bar",
+            "tag:",
+            r#"camera:"SM"#,
+            "date:2019-13",
+            "date:2019-02-29",
+            "date:..",
+            "mp:12",
+            "mp:..",
+            "mp:inf..",
+            "mp:1e999..",
+            "mp:nan..5",
+            "tag:x -",
+            r#""""#,
+        ] {
+            assert!(Query::parse(bad).is_err(), "query: {:?}", bad);
+        }
+    }
+
+    #[test]
+    fn query_files() {
+        // arrange
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let files = [
+            ("hash-a", "disk-1", "2019/06/IMG_1.jpg", Some("Samsung")),
+            ("hash-b", "disk-1", "Signal/signal-2019-07.jpg", None),
+            ("hash-c", "disk-2", "2020/old.jpg", Some("Canon")),
+        ];
+        for (i, (hash, marker, path, make)) in files.into_iter().enumerate() {
+            let info = FileInfo {
+                hash: hash.to_string(),
+                date: Some(
+                    NaiveDate::from_ymd(2019 + i as i32 / 2, 6 + i as u32, 1).and_hms(0, 0, 0),
+                ),
+                camera: CameraInfo {
+                    make: make.map(String::from),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            db::upsert(&conn, marker, path, &info).unwrap();
+        }
+        db::create_tag(&conn, "holiday").unwrap();
+        db::set_file_tag(&conn, "hash-b", "holiday", true).unwrap();
+        db::set_file_tag(&conn, "hash-c", "hidden", true).unwrap();
+        conn.execute("UPDATE file SET place = 'Kraków' WHERE hash = 'hash-a'", [])
+            .unwrap();
+
+        let hashes = |text: &str| {
+            let filter = db::Filter {
+                query: Query::parse(text).unwrap(),
+                ..Default::default()
+            };
+            let n = db::count_files(&conn, &filter).unwrap();
+            (0..n)
+                .map(|i| db::details(&conn, &filter, i).unwrap().unwrap().hash)
+                .collect::<Vec<_>>()
+        };
+
+        // assert
+
+        assert_eq!(hashes(""), vec!["hash-a", "hash-b"]);
+        assert_eq!(hashes("tag:holiday"), vec!["hash-b"]);
+        assert_eq!(hashes("-tag:holiday"), vec!["hash-a"]);
+        // Hidden files are shown when asked for explicitly.
+        assert_eq!(hashes("tag:hidden"), vec!["hash-c"]);
+        assert_eq!(hashes("date:2019-07..2020"), vec!["hash-b"]);
+        assert_eq!(hashes("marker:disk-1 path:*/IMG_*"), vec!["hash-a"]);
+        assert_eq!(hashes("signal"), vec!["hash-b"]);
+        // Words are matched ignoring case, unlike path patterns.
+        assert_eq!(hashes("SIGNAL"), vec!["hash-b"]);
+        assert_eq!(hashes("path:SIGNAL*"), Vec::<String>::new());
+        // Files with unknown camera don't match, unless negated.
+        assert_eq!(hashes("camera:sams"), vec!["hash-a"]);
+        assert_eq!(hashes("-camera:sams"), vec!["hash-b"]);
+        // Case of non-ASCII letters is ignored too.
+        assert_eq!(hashes("place:KRAKÓW"), vec!["hash-a"]);
+    }
+}
@@ -0,0 +1,354 @@
+//! Copying files which are present on too few markers onto another marker tree.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Component, Path};
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDateTime;
+use itertools::Itertools;
+use rusqlite::{params, Connection};
+
+use crate::db::{self, SyncedDb};
+use crate::interlude::*;
+use crate::model::FileStat;
+use crate::scanning::{find_file, hash_matches, Tree};
+
+/// Template used when none is configured for target marker.
+pub const DEFAULT_TEMPLATE: &str = "{path}";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Stats {
+    pub copied: u64,
+    pub bytes: u64,
+    /// Files already present at target path with matching contents, just recorded in DB.
+    pub found: u64,
+    pub skipped: u64,
+}
+
+/// A file which should be copied, with all its known locations.
+struct Candidate {
+    hash: String,
+    date: Option<NaiveDateTime>,
+    locations: Vec<(String, String)>,
+}
+
+/// Copy files present on fewer than `min_markers` distinct markers (and not yet present on
+/// `target`) from any of the `sources` trees onto `target`, at paths built from `template` (see
+/// [`expand_template`]). Each copy is verified by re-hashing before it's recorded in DB.
+pub fn replicate(
+    db: &SyncedDb,
+    sources: &[Tree],
+    target: &Tree,
+    template: &str,
+    min_markers: u32,
+    dry_run: bool,
+) -> Result<Stats> {
+    let candidates = {
+        let db = db.lock().unwrap();
+        candidates(&db, &target.marker, min_markers)?
+    };
+    let mut stats = Stats::default();
+    for c in candidates {
+        // The target path follows the location actually copied from.
+        let ((_, source_relative), source) = match find_file(sources, &c.locations) {
+            Some(found) => found,
+            None => {
+                iprintln!("Skipping " c.hash ": not found on any connected marker");
+                stats.skipped += 1;
+                continue;
+            }
+        };
+        let relative = match expand_template(template, source_relative, c.date)? {
+            Some(relative) => relative,
+            None => {
+                iprintln!("Skipping " source;? ": unknown date required by template");
+                stats.skipped += 1;
+                continue;
+            }
+        };
+        let dest = target.path_of(&relative);
+        iprintln!(source;? " -> " dest;?);
+        if dry_run {
+            continue;
+        }
+
+        // Don't trust the source blindly, it could have been corrupted since last scan.
+        let buf = fs::read(&source).with_context(|| ifmt!("reading " source;?))?;
+        if !hash_matches(&buf, &c.hash) {
+            ieprintln!("Skipping " source;? ": contents don't match hash " c.hash);
+            stats.skipped += 1;
+            continue;
+        }
+
+        if dest.exists() {
+            let existing = fs::read(&dest).with_context(|| ifmt!("reading " dest;?))?;
+            if !hash_matches(&existing, &c.hash) {
+                ieprintln!("Skipping " source;? ": different file already exists at " dest;?);
+                stats.skipped += 1;
+                continue;
+            }
+            stats.found += 1;
+        } else {
+            write_verified(&dest, &buf, &c.hash)?;
+            stats.copied += 1;
+            stats.bytes += buf.len() as u64;
+        }
+
+        let stat = fs::metadata(&dest)
+            .and_then(|m| FileStat::of(&m))
+            .with_context(|| ifmt!("reading metadata of " dest;?))?;
+        let db = db.lock().unwrap();
+        db::add_location(&db, &target.marker, &relative, &c.hash)?;
+        db::set_location_stat(&db, &target.marker, &relative, &stat)?;
+    }
+    Ok(stats)
+}
+
+fn candidates(db: &Connection, target: &str, min_markers: u32) -> Result<Vec<Candidate>> {
+    let mut query = db.prepare(
+        "WITH counts AS (
+            SELECT file_id, COUNT(DISTINCT backend_tag) AS markers
+            FROM location
+            GROUP BY file_id
+        )
+        SELECT hash, date, backend_tag, path
+            FROM location
+            JOIN counts USING (file_id)
+            JOIN file ON file.rowid = file_id
+            WHERE markers < ?
+            AND file_id NOT IN (SELECT file_id FROM location WHERE backend_tag = ?)
+            ORDER BY file_id, backend_tag, path",
+    )?;
+    let rows = query
+        .query_map(params![min_markers, target], |row| {
+            Ok((row.get(0)?, row.get(1)?, (row.get(2)?, row.get(3)?)))
+        })?
+        .collect::<rusqlite::Result<Vec<(String, Option<NaiveDateTime>, _)>>>()?;
+    Ok(rows
+        .into_iter()
+        .group_by(|(hash, date, _)| (hash.clone(), *date))
+        .into_iter()
+        .map(|((hash, date), rows)| Candidate {
+            hash,
+            date,
+            locations: rows.map(|(_, _, location)| location).collect(),
+        })
+        .collect())
+}
+
+/// Write `buf` to a temporary file next to `dest`, verify its contents match `expected_hash`, then
+/// rename it to `dest`. This way an interrupted copy never shows up at `dest`.
+pub(crate) fn write_verified(dest: &Path, buf: &[u8], expected_hash: &str) -> Result<()> {
+    let dir = dest
+        .parent()
+        .with_context(|| ifmt!("no parent directory of " dest;?))?;
+    fs::create_dir_all(dir).with_context(|| ifmt!("creating directory " dir;?))?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(dest.file_name().unwrap_or_default());
+    tmp_name.push(".backer-tmp");
+    let tmp = dir.join(tmp_name);
+
+    let result = (|| {
+        let mut file = File::create(&tmp)?;
+        file.write_all(buf)?;
+        file.sync_all()?;
+        drop(file);
+        let written = fs::read(&tmp)?;
+        if !hash_matches(&written, expected_hash) {
+            bail!(
+                "verification failed, contents of copy don't match hash {}",
+                expected_hash
+            );
+        }
+        fs::rename(&tmp, dest)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result.with_context(|| ifmt!("copying to " dest;?))
+}
+
+/// Build a slash-separated relative path from `template`, replacing the following placeholders:
+/// `{path}` with the `source` relative path; `{dir}` and `{name}` with its directory and file name;
+/// `{year}`, `{month}`, `{day}` with the file's date. Returns None if the template needs a date,
+/// but the file has none. Fails if the result isn't a path inside the target tree (e.g. is absolute
+/// or contains `..`).
+pub fn expand_template(
+    template: &str,
+    source: &str,
+    date: Option<NaiveDateTime>,
+) -> Result<Option<String>> {
+    let (dir, name) = source.rsplit_once('/').unwrap_or(("", source));
+    let mut path = template
+        .replace("{path}", source)
+        .replace("{dir}", dir)
+        .replace("{name}", name);
+    for (placeholder, format) in [("{year}", "%Y"), ("{month}", "%m"), ("{day}", "%d")] {
+        if path.contains(placeholder) {
+            let formatted = match date {
+                Some(date) => date.format(format).to_string(),
+                None => return Ok(None),
+            };
+            path = path.replace(placeholder, &formatted);
+        }
+    }
+    // Collapse empty path segments, e.g. from an empty {dir}.
+    let relative = path.split('/').filter(|s| !s.is_empty()).join("/");
+    let outside = Path::new(template).has_root()
+        || Path::new(&relative).components().any(|c| {
+            matches!(
+                c,
+                Component::ParentDir | Component::RootDir | Component::Prefix(_)
+            )
+        });
+    if outside {
+        bail!(
+            "path {:?} expanded from template {:?} leads outside of marker tree",
+            relative,
+            template
+        );
+    }
+    Ok(Some(relative))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+    use tempfile::tempdir;
+
+    use crate::model::FileInfo;
+    use crate::scanning::{hash, tree_at};
+
+    use super::*;
+
+    #[test]
+    fn expand_templates() {
+        let date = NaiveDate::from_ymd(2017, 4, 26).and_hms(12, 45, 22);
+        let source = "incoming/20170426_124522.jpg";
+        assert_eq!(
+            expand_template("{path}", source, None).unwrap(),
+            Some(source.to_string())
+        );
+        assert_eq!(
+            expand_template("{year}/{month}/{name}", source, Some(date)).unwrap(),
+            Some("2017/04/20170426_124522.jpg".to_string())
+        );
+        assert_eq!(
+            expand_template("{dir}/{day}-{name}", "x.jpg", Some(date)).unwrap(),
+            Some("26-x.jpg".to_string())
+        );
+        assert_eq!(
+            expand_template("{year}/{name}", source, None).unwrap(),
+            None
+        );
+        for bad in ["../{name}", "/tmp/{name}", "{year}/../../{path}"] {
+            assert!(
+                expand_template(bad, source, Some(date)).is_err(),
+                "template: {:?}",
+                bad
+            );
+        }
+        assert!(expand_template("{path}", "a/../../x.jpg", None).is_err());
+    }
+
+    #[test]
+    fn replicate_to_other_tree() {
+        // arrange
+
+        let (root_a, root_b) = (tempdir().unwrap(), tempdir().unwrap());
+        let tree_a = tree_at(root_a.path(), "marker-a");
+        let tree_b = tree_at(root_b.path(), "marker-b");
+        let contents = b"fake jpeg contents";
+        fs::create_dir(root_a.path().join("dir")).unwrap();
+        fs::write(root_a.path().join("dir/foo.jpg"), contents).unwrap();
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        db::upsert(
+            &conn,
+            "marker-a",
+            "dir/foo.jpg",
+            &FileInfo {
+                hash: hash(contents),
+                date: Some(NaiveDate::from_ymd(2019, 6, 1).and_hms(0, 0, 0)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let db = Arc::new(Mutex::new(conn));
+
+        // act
+
+        let stats = replicate(
+            &db,
+            &[tree_a.clone(), tree_b.clone()],
+            &tree_b,
+            "{year}/{month}/{name}",
+            2,
+            false,
+        )
+        .unwrap();
+
+        // assert
+
+        assert_eq!(
+            stats,
+            Stats {
+                copied: 1,
+                bytes: contents.len() as u64,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            fs::read(root_b.path().join("2019/06/foo.jpg")).unwrap(),
+            contents
+        );
+        assert!(!root_b.path().join("2019/06/.foo.jpg.backer-tmp").exists());
+        let conn = db.lock().unwrap();
+        assert_eq!(db::exists(&conn, "marker-b", "2019/06/foo.jpg"), Ok(true));
+        drop(conn);
+
+        // Nothing more to do on second run.
+        let stats = replicate(&db, &[tree_a], &tree_b, "{path}", 2, false).unwrap();
+        assert_eq!(stats, Stats::default());
+    }
+
+    #[test]
+    fn replicate_following_location_found() {
+        // arrange
+
+        let (root_a, root_b) = (tempdir().unwrap(), tempdir().unwrap());
+        let tree_a = tree_at(root_a.path(), "marker-a");
+        let tree_b = tree_at(root_b.path(), "marker-b");
+        let contents = b"fake jpeg contents";
+        fs::create_dir(root_a.path().join("dir")).unwrap();
+        fs::write(root_a.path().join("dir/foo.jpg"), contents).unwrap();
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let info = FileInfo {
+            hash: hash(contents),
+            ..Default::default()
+        };
+        // The copy on a disconnected marker is listed first.
+        db::upsert(&conn, "marker-0", "elsewhere/foo.jpg", &info).unwrap();
+        db::upsert(&conn, "marker-a", "dir/foo.jpg", &info).unwrap();
+        let db = Arc::new(Mutex::new(conn));
+
+        // act
+
+        let stats = replicate(&db, &[tree_a], &tree_b, "{path}", 3, false).unwrap();
+
+        // assert
+
+        assert_eq!(stats.copied, 1);
+        assert_eq!(
+            fs::read(root_b.path().join("dir/foo.jpg")).unwrap(),
+            contents
+        );
+        assert!(!root_b.path().join("elsewhere").exists());
+    }
+}
@@ -1,48 +1,87 @@
 use std::fs::{self, File};
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-use anyhow::{Context, Result};
-use chrono::{NaiveDate, NaiveDateTime};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use exif::{Exif, Reader as ExifReader};
 use image::imageops::FilterType;
 use image::io::Reader as ImageReader;
+use image::{DynamicImage, GenericImageView};
 use path_slash::{PathBufExt, PathExt};
 use rayon::prelude::*;
 use rusqlite::Connection as DbConnection;
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
 use thiserror::Error;
 
 use crate::config::{self, Config, DatePath};
+use crate::dates::{self, Candidate, DateSource, Timestamp};
 use crate::db::{self, SyncedDb};
+use crate::geocode;
 use crate::imaging::*;
 use crate::interlude::*;
 use crate::model;
 use crate::pathwalk::{matcher, walker};
+use crate::video;
 
-pub fn scan(db: SyncedDb, config: Config) -> Result<()> {
-    let date_paths = config.date_path;
-    for err in config
+/// Scan all connected marker trees and update the DB. Files with size and modification time
+/// unchanged since last scan are skipped, unless `deep` is set - then all files are re-read,
+/// verified against hashes stored in DB, and refreshed.
+pub fn scan(db: SyncedDb, config: Config, deep: bool) -> Result<()> {
+    for source in config.unused_date_sources() {
+        ieprintln!(
+            "Warning: date source " source.name();? " is ignored, as fs-date-fallback is off"
+        );
+    }
+    let errors = config
         .markers
         .disk
-        .into_par_iter()
+        .par_iter()
         .enumerate()
-        .filter_map(|(i, marker)| process_tree(i, marker, date_paths.clone(), db.clone()).err())
-        .collect::<Vec<_>>()
-    {
+        .filter_map(|(i, marker)| process_tree(i, marker, &config, db.clone(), deep).err())
+        .collect::<Vec<_>>();
+    for err in &errors {
         ieprintln!("Error: " err);
     }
+    if !errors.is_empty() {
+        bail!("failed scanning {} tree(s)", errors.len());
+    }
+
+    if let Some(path) = &config.places_file {
+        if let Err(err) = name_places(&db, path) {
+            ieprintln!("\nWarning: places of files not named: " error_chain(&err));
+        }
+    }
+
+    let legacy = db::count_legacy_hashes(&db.lock().unwrap())?;
+    if legacy > 0 {
+        iprintln!("\n" legacy " file(s) still have legacy SHA-1 hashes, use `scan --deep` to upgrade them.");
+    }
 
     Ok(())
 }
 
+fn name_places(db: &SyncedDb, path: &Path) -> Result<()> {
+    // Reading the places takes a while, so skip it if there's nothing to name.
+    if geocode::count_unnamed(&db.lock().unwrap())? == 0 {
+        return Ok(());
+    }
+    let places = geocode::Places::read(path)?;
+    let named = geocode::assign_places(&db.lock().unwrap(), &places, false)?;
+    iprintln!("\nNamed places of " named " file(s).");
+    Ok(())
+}
+
 pub fn process_tree(
     i: usize,
     marker_path: impl AsRef<Path>,
-    date_paths_per_marker: config::DatePathsPerMarker,
+    config: &Config,
     db: Arc<Mutex<DbConnection>>,
+    deep: bool,
 ) -> Result<()> {
-    let m = Tree::open(marker_path, &date_paths_per_marker);
+    let m = Tree::open(marker_path, &config.date_path);
     if let Err(TreeError::NotFound { .. }) = &m {
         iprintln!("\nSkipping tree: " error_chain(&m.unwrap_err().into()));
         return Ok(());
@@ -53,32 +92,69 @@ pub fn process_tree(
     // Match any date-path config to marker.
     iprintln!("\nDate-paths at " tree.marker;? ": " tree.date_paths;?);
 
-    // Stage 1: add not-yet-known files into DB
-    stage1(i, &tree, &db, OnExisting::Skip)?;
+    // Stage 1: add not-yet-known files into DB, record changed ones as integrity incidents
+    let scope = Scope::new(config, &tree.marker);
+    let skipped = stage1(i, &tree, &db, &scope, OnExisting::Skip)?;
+    if let Some(min) = scope.ignore_small {
+        iprintln!("\nSkipped " skipped " image(s) smaller than " min.w "x" min.h " at " tree.marker;?);
+    }
 
     // Stage 2: check if all files from DB are present on disk, delete entries for any missing
-    stage2(&tree, &db)?;
+    stage2(&tree, &db, deep)?;
 
-    // Stage 3: scan all files once more and refresh them in DB
-    stage1(i, &tree, &db, OnExisting::Refresh)?;
+    if deep {
+        // Stage 3: scan all files once more and refresh them in DB
+        stage1(i, &tree, &db, &scope, OnExisting::Refresh)?;
+    }
 
     Ok(())
 }
 
 #[derive(PartialEq)]
 enum OnExisting {
+    /// Skip files already known at their location, unless they changed since last scan.
     Skip,
     Refresh,
 }
 
+/// Which files found in a tree are tracked.
+struct Scope<'a> {
+    image_extensions: &'a [String],
+    video_extensions: &'a [String],
+    /// Images smaller than this are skipped, as they're probably thumbnails or icons.
+    ignore_small: Option<config::MinSize>,
+    date_priority: &'a [DateSource],
+    max_date_spread: chrono::Duration,
+    /// Whether filesystem timestamps of files are recorded as their candidate dates.
+    fs_dates: bool,
+}
+
+impl<'a> Scope<'a> {
+    fn new(config: &'a Config, marker: &str) -> Self {
+        Self {
+            image_extensions: &config.image_extensions,
+            video_extensions: &config.video_extensions,
+            ignore_small: config.ignore_small_at(marker),
+            date_priority: &config.date_priority,
+            max_date_spread: config.max_date_spread(),
+            fs_dates: config.fs_date_fallback_at(marker),
+        }
+    }
+}
+
+/// Add files from `tree` to DB. Returns number of images skipped for being too small.
 fn stage1(
     i: usize,
     tree: &Tree,
     db: &Arc<Mutex<DbConnection>>,
+    scope: &Scope,
     on_existing: OnExisting,
-) -> Result<()> {
+) -> Result<u64> {
+    let extensions = [scope.image_extensions, scope.video_extensions].concat();
+    let video_matcher = matcher::CaseInsensitiveExtensions::boxed(scope.video_extensions);
+    let mut skipped_small = 0;
     // TODO[LATER]: in parallel thread, count all matching files, then when done start showing progress bar/percentage
-    for entry in tree.iter() {
+    for entry in tree.iter(&extensions) {
         let entry = match entry {
             // TODO[LATER]: use `let else` once stable
             Ok(entry) => entry,
@@ -93,92 +169,243 @@ fn stage1(
             .to_slash()
             .with_context(|| ifmt!("Failed to convert path " os_relative;? " to slash-based"))?;
 
-        // Read file contents to memory.
-        let buf = fs::read(&path)?;
+        let metadata = fs::metadata(&path).with_context(|| ifmt!("reading metadata of " path;?))?;
+        let stat =
+            model::FileStat::of(&metadata).with_context(|| ifmt!("reading metadata of " path;?))?;
 
-        // If file already exists in DB, skip it.
+        // If file already exists in DB unchanged and with an up to date thumbnail, skip it.
         let db_readable = db.lock().unwrap();
-        if db::exists(&db_readable, &tree.marker, &relative)? && on_existing == OnExisting::Skip {
+        let old_stat = db::location_stat(&db_readable, &tree.marker, &relative)?;
+        if on_existing == OnExisting::Skip
+            && old_stat == Some(stat)
+            && !db::thumb_outdated(&db_readable, &tree.marker, &relative)?
+        {
             print!(".");
             io::stdout().flush()?;
             continue;
         }
+        // Don't overwrite expected hash of a file until the user decides what to do with it.
+        if db::has_open_incident(&db_readable, &tree.marker, &relative)? {
+            continue;
+        }
+        let expected_hash = match old_stat {
+            Some(old_stat) if old_stat != stat => {
+                db::location_hash(&db_readable, &tree.marker, &relative)?
+            }
+            _ => None,
+        };
         drop(db_readable);
 
-        // Calculate sha1 hash of the file contents.
-        // TODO[LATER]: maybe switch to a secure hash (sha2 or other, see: https://github.com/RustCrypto/hashes)
-        let hash = hash(&buf);
-        // let hash = format!("{:x}", Sha1::digest(&buf));
-
-        // FIXME: if image is very small, it's probably a thumbnail already and we don't want to archive it
-
-        // Does the JPEG have Exif block? We assume it'd be the most reliable source of metadata.
-        let exif = ExifReader::new()
-            .read_from_container(&mut io::Cursor::new(&buf))
-            .ok();
-        let date = try_deduce_date(exif.as_ref(), &relative, tree.date_paths.iter());
-        // // TODO[LATER]: use some orientation enum / stricter type instead of raw u16
-        // let orientation = exif.as_ref().and_then(|v| v.orientation()).unwrap_or(1);
-
-        // Parse the file as an image and create thumbnail, or skip with warning if impossible.
-        let img = match ImageReader::new(io::Cursor::new(&buf))
-            .with_guessed_format()?
-            .decode()
-        {
-            Ok(img) => img,
-            Err(err) => {
-                // TODO[LATER]: use termcolor crate to print errors in red
-                // FIXME[LATER]: resolve JPEG decoding error: "spectral selection is not allowed in non-progressive scan"
-                ieprintln!("\nFailed to decode JPEG " &path;? ", skipping: " err);
+        // A known file changed since last scan - maybe edited on purpose, but maybe overwritten
+        // by mistake. Keep its expected hash until the user resolves the incident.
+        if let Some(expected_hash) = expected_hash {
+            let (hashes, _) = File::open(&path)
+                .and_then(|mut file| Hashes::of_reader(&mut file))
+                .with_context(|| ifmt!("reading " path;?))?;
+            if !hashes.matches(&expected_hash) {
+                iprintln!("\nCHANGED: " hashes.current " != " expected_hash " @ " path;?);
+                let db = db.lock().unwrap();
+                db::add_incident(
+                    &db,
+                    &tree.marker,
+                    &relative,
+                    &expected_hash,
+                    &hashes.current,
+                    true,
+                )?;
                 continue;
             }
+        }
+
+        let is_video = video_matcher.matches(&entry);
+        if let (false, Some(min)) = (is_video, scope.ignore_small) {
+            // Only the header is read here, so this is cheap compared to a full scan.
+            let small = ImageReader::open(&path)
+                .and_then(|r| r.with_guessed_format())
+                .ok()
+                .and_then(|r| r.into_dimensions().ok())
+                .map_or(false, |(w, h)| !min.fits(w, h));
+            if small {
+                skipped_small += 1;
+                continue;
+            }
+        }
+
+        let (mut info, legacy_hash, mut dates) = if is_video {
+            scan_video(tree, &path, &relative)?
+        } else {
+            scan_image(tree, &path, &relative)?
         };
-        // let thumb = img.resize(200, 200, FilterType::Lanczos3);
-        let thumb = img.resize(200, 200, FilterType::CatmullRom);
-        // FIXME[LATER]: fix the thumbnail's orientation
-        let mut thumb_jpeg = Vec::<u8>::new();
-        thumb.write_to(&mut thumb_jpeg, image::ImageOutputFormat::Jpeg(90))?;
-
-        // Add image entry to DB.
-        let info = model::FileInfo {
-            hash: hash.clone(),
-            date,
-            thumb: thumb_jpeg,
-        };
+        if scope.fs_dates {
+            dates.extend(fs_date_candidates(&metadata));
+        }
+        info.date = dates::pick(&dates, scope.date_priority).map(|(_, d)| d.local);
+
+        // Add file entry to DB.
         let db_writable = db.lock().unwrap();
+        db::upgrade_hash(&db_writable, &legacy_hash, &info.hash)?;
         db::upsert(&db_writable, &tree.marker, &relative, &info)?;
+        db::set_location_stat(&db_writable, &tree.marker, &relative, &stat)?;
+        db::set_date_candidates(&db_writable, &tree.marker, &relative, &dates)?;
+        db::refresh_date(
+            &db_writable,
+            &info.hash,
+            scope.date_priority,
+            scope.max_date_spread,
+        )?;
         drop(db_writable);
 
         // Print some debugging info, showing which marker is still being processed.
         iprint!(i);
         io::stdout().flush()?;
-        // println!("{} {} {:?} {:?}", &hash, path.display(), date.map(|d| d.to_string()), orientation);
     }
 
-    Ok(())
+    Ok(skipped_small)
 }
 
-pub fn stage2(tree: &Tree, db: &Arc<Mutex<DbConnection>>) -> Result<()> {
+/// Returns info about the file (without a date), its legacy hash, and candidate dates.
+fn scan_image(
+    tree: &Tree,
+    path: &Path,
+    relative: &str,
+) -> Result<(model::FileInfo, String, Vec<Candidate>)> {
+    // Read file contents to memory.
+    let buf = fs::read(path)?;
+
+    let hashes = Hashes::of(&buf);
+
+    // Does the image have Exif block? We assume it'd be the most reliable source of metadata.
+    let exif = ExifReader::new()
+        .read_from_container(&mut io::Cursor::new(&buf))
+        .ok();
+    let dates = date_candidates(exif.as_ref(), relative, &tree.date_paths);
+    let orientation = exif.as_ref().and_then(|v| v.orientation());
+    let gps = exif.as_ref().and_then(|v| v.gps_position());
+
+    let mut info = model::FileInfo {
+        hash: hashes.current,
+        size: Some(buf.len() as u64),
+        gps,
+        camera: exif.as_ref().map(|v| v.camera()).unwrap_or_default(),
+        ..Default::default()
+    };
+
+    // Parse the file as an image and create thumbnail, or track it without one if impossible.
+    match decode(&buf, orientation) {
+        Ok(img) => {
+            info.thumb = thumbnail(&img)?;
+            info.phash = Some(dhash(&img));
+            info.width = Some(img.width());
+            info.height = Some(img.height());
+        }
+        Err(err) => {
+            // TODO[LATER]: use termcolor crate to print errors in red
+            // FIXME[LATER]: resolve JPEG decoding error: "spectral selection is not allowed in non-progressive scan"
+            ieprintln!("\nFailed to decode image " path;? ", no thumbnail: " err);
+        }
+    }
+
+    Ok((info, hashes.legacy, dates))
+}
+
+/// Videos can be huge, so they're hashed while streaming, and only their headers are parsed.
+/// They get no thumbnail - the gallery shows a placeholder with their duration instead.
+// TODO[LATER]: extract a keyframe as a thumbnail
+fn scan_video(
+    tree: &Tree,
+    path: &Path,
+    relative: &str,
+) -> Result<(model::FileInfo, String, Vec<Candidate>)> {
+    let mut file = File::open(path).with_context(|| ifmt!("opening " path;?))?;
+    let (hashes, size) = Hashes::of_reader(&mut file).with_context(|| ifmt!("reading " path;?))?;
+    let video = match video::read_info(&mut file) {
+        Ok(video) => video,
+        Err(err) => {
+            ieprintln!("\nFailed to read video metadata of " path;? ": " err);
+            Default::default()
+        }
+    };
+    let mut dates = date_candidates(None, relative, &tree.date_paths);
+    // Videos record creation time in UTC.
+    dates.extend(
+        video
+            .created
+            .map(|d| (DateSource::Video, Timestamp::from_utc(d))),
+    );
+    let info = model::FileInfo {
+        hash: hashes.current,
+        size: Some(size),
+        duration_ms: video.duration_ms,
+        video: true,
+        ..Default::default()
+    };
+    Ok((info, hashes.legacy, dates))
+}
+
+/// Decode image from `buf` in any supported format, transformed upright.
+fn decode(buf: &[u8], orientation: Option<Orientation>) -> Result<DynamicImage> {
+    let img = ImageReader::new(io::Cursor::new(buf))
+        .with_guessed_format()?
+        .decode()?;
+    Ok(match orientation {
+        Some(orientation) => orientation.apply(img),
+        None => img,
+    })
+}
+
+/// Render the image as a JPEG thumbnail.
+fn thumbnail(img: &DynamicImage) -> Result<Vec<u8>> {
+    // let thumb = img.resize(200, 200, FilterType::Lanczos3);
+    let thumb = img.resize(200, 200, FilterType::CatmullRom);
+    // JPEG supports neither alpha channel nor 16-bit colors, which e.g. PNGs can have.
+    let thumb = DynamicImage::ImageRgb8(thumb.to_rgb8());
+    let mut thumb_jpeg = Vec::<u8>::new();
+    thumb.write_to(&mut thumb_jpeg, image::ImageOutputFormat::Jpeg(90))?;
+    Ok(thumb_jpeg)
+}
+
+/// Delete locations of files no longer present in the `tree` from DB. If `deep` is set, also
+/// read all present files and record those with contents not matching hashes in DB as
+/// incidents.
+pub fn stage2(tree: &Tree, db: &Arc<Mutex<DbConnection>>, deep: bool) -> Result<()> {
     for item in db::hashes(db.clone(), &tree.marker) {
         let (relative_path, db_hash) = item?;
 
-        let path = tree.root.join(PathBuf::from_slash(&relative_path));
+        let path = tree.path_of(&relative_path);
+
+        if !deep {
+            if !path.is_file() {
+                let db = db.lock().unwrap();
+                db::remove(&db, &tree.marker, &relative_path)?;
+            }
+            continue;
+        }
 
-        // Try reading file contents to memory.
-        let contents = match fs::read(&path) {
-            Ok(data) => Some(data),
+        // Try reading file contents.
+        let contents = match File::open(&path) {
+            Ok(mut file) => Some(Hashes::of_reader(&mut file)?.0),
             Err(err) if err.kind() == io::ErrorKind::NotFound => None,
             Err(err) => return Err(anyhow!(err)),
         };
-        if let Some(data) = contents {
-            let disk_hash = hash(&data);
-
-            if disk_hash == db_hash {
+        if let Some(hashes) = contents {
+            if hashes.matches(&db_hash) {
                 print!(",");
                 io::stdout().flush()?;
             } else {
+                let disk_hash = hashes.current;
                 iprintln!("\nBAD HASH: " disk_hash " != " db_hash " @ " path;?);
-                // iprintln!("* " db_hash " @ " path;?);
+                let stat = fs::metadata(&path).and_then(|m| model::FileStat::of(&m))?;
+                let db = db.lock().unwrap();
+                let stat_changed =
+                    db::location_stat(&db, &tree.marker, &relative_path)? != Some(stat);
+                db::add_incident(
+                    &db,
+                    &tree.marker,
+                    &relative_path,
+                    &db_hash,
+                    &disk_hash,
+                    stat_changed,
+                )?;
             }
         } else {
             let db = db.lock().unwrap();
@@ -236,10 +463,51 @@ impl Tree {
         })
     }
 
-    pub fn iter(&self) -> walker::FilesIterator {
-        let jpeg_matcher = matcher::CaseInsensitiveExtensions::boxed(["jpg", "jpeg"]);
-        walker::Files::new(&self.root, [jpeg_matcher]).into_iter()
+    /// Full path of a file in the tree, given its slash-separated `relative` path.
+    pub fn path_of(&self, relative: &str) -> PathBuf {
+        self.root.join(PathBuf::from_slash(relative))
     }
+
+    /// Iterate over files in the tree having any of the (case-insensitive) `extensions`.
+    pub fn iter(&self, extensions: &[String]) -> walker::FilesIterator {
+        let matcher = matcher::CaseInsensitiveExtensions::boxed(extensions);
+        walker::Files::new(&self.root, [matcher]).into_iter()
+    }
+}
+
+/// Tree of marker `marker` rooted at `root`, with a marker file written there - for tests.
+#[cfg(test)]
+pub(crate) fn tree_at(root: &Path, marker: &str) -> Tree {
+    let marker_path = root.join("backer-id.json");
+    fs::write(&marker_path, format!(r#"{{"id": "{}"}}"#, marker)).unwrap();
+    Tree::open(marker_path, &config::DatePathsPerMarker::new()).unwrap()
+}
+
+/// Open trees of all markers from `config` which are currently connected.
+pub fn connected_trees(config: &Config) -> Vec<Tree> {
+    config
+        .markers
+        .disk
+        .iter()
+        .filter_map(|marker_path| Tree::open(marker_path, &config.date_path).ok())
+        .collect()
+}
+
+/// Find first of the `(marker, path)` locations that exists as a file in any of the `trees`.
+/// Returns the location found, and the path of the file.
+pub fn find_file<'a>(
+    trees: &[Tree],
+    locations: &'a [(String, String)],
+) -> Option<(&'a (String, String), PathBuf)> {
+    locations.iter().find_map(|location| {
+        let (marker, relative) = location;
+        trees
+            .iter()
+            .filter(|tree| &tree.marker == marker)
+            .map(|tree| tree.path_of(relative))
+            .find(|path| path.is_file())
+            .map(|path| (location, path))
+    })
 }
 
 // TODO[LATER]: accept Path and return Result<(Path,...)> with proper lifetime
@@ -263,49 +531,147 @@ fn marker_read(file_path: &Path) -> Result<(PathBuf, String)> {
     Ok((parent.to_owned(), m.id))
 }
 
+/// Prefix of hashes calculated with SHA-256. Hashes without a prefix were calculated with SHA-1
+/// by older versions of backer; they get replaced with SHA-256 ones when files are re-scanned.
+pub const SHA256_PREFIX: &str = "sha256:";
+
 /// Calculate a hash of the buf contents, and return it in a pretty-printed format for storing in
 /// the DB.
 pub fn hash(buf: &[u8]) -> String {
-    // TODO[LATER]: maybe switch to a secure hash (sha2 or other, see: https://github.com/RustCrypto/hashes)
-    format!("{:x}", Sha1::digest(buf))
+    format!("{}{:x}", SHA256_PREFIX, Sha256::digest(buf))
+}
+
+/// Check if contents of buf match `expected` hash from DB, whichever algorithm it was made with.
+pub fn hash_matches(buf: &[u8], expected: &str) -> bool {
+    if expected.starts_with(SHA256_PREFIX) {
+        hash(buf) == expected
+    } else {
+        format!("{:x}", Sha1::digest(buf)) == expected
+    }
 }
 
-/// Try hard to find out some datetime info from either `exif` data, or `relative_path` of the file.
-fn try_deduce_date<'a>(
+/// Hashes of some contents, calculated with all algorithms which can be found in DB.
+// TODO[LATER]: drop SHA-1 once no legacy hashes are left in DB (see `db::count_legacy_hashes`)
+pub struct Hashes {
+    /// Hash as calculated by [`hash`].
+    pub current: String,
+    /// SHA-1 hash, as stored in DB by older versions of backer.
+    pub legacy: String,
+}
+
+impl Hashes {
+    pub fn of(buf: &[u8]) -> Self {
+        Self {
+            current: hash(buf),
+            legacy: format!("{:x}", Sha1::digest(buf)),
+        }
+    }
+
+    /// Calculate hashes while reading the contents in chunks. Returns the hashes and number of
+    /// bytes read.
+    pub fn of_reader(r: &mut impl io::Read) -> io::Result<(Self, u64)> {
+        let (mut sha256, mut sha1) = (Sha256::new(), Sha1::new());
+        let mut buf = vec![0u8; 1 << 20];
+        let mut total = 0;
+        loop {
+            let n = match r.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            sha256.update(&buf[..n]);
+            sha1.update(&buf[..n]);
+            total += n as u64;
+        }
+        let hashes = Self {
+            current: format!("{}{:x}", SHA256_PREFIX, sha256.finalize()),
+            legacy: format!("{:x}", sha1.finalize()),
+        };
+        Ok((hashes, total))
+    }
+
+    /// Check if the contents match `expected` hash from DB, whichever algorithm it was made with.
+    pub fn matches(&self, expected: &str) -> bool {
+        expected == self.current || expected == self.legacy
+    }
+}
+
+/// Collect candidate dates of a file from its `exif` data, and its `relative_path`.
+pub(crate) fn date_candidates(
     exif: Option<&Exif>,
     relative_path: &str,
-    date_paths: impl Iterator<Item = &'a config::DatePath>,
-) -> Option<NaiveDateTime> {
+    date_paths: &[DatePath],
+) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
     if let Some(exif) = exif {
         use exif::Tag;
         // TODO[LATER]: are ther other fields we could try?
-        if let Some(d) = vec![Tag::DateTime, Tag::DateTimeOriginal]
-            .into_iter()
-            .filter_map(|tag| exif.datetime(tag))
-            .filter_map(|dt| dt.to_naive_opt())
-            .next()
-        {
-            return Some(d);
+        let gps = exif.gps_datetime();
+        for (tag, source) in [
+            (Tag::DateTimeOriginal, DateSource::ExifOriginal),
+            (Tag::DateTime, DateSource::ExifDatetime),
+        ] {
+            if let Some(mut d) = exif.datetime(tag).and_then(|dt| dt.to_timestamp_opt()) {
+                // Older cameras don't record the offset, but it can be deduced from GPS time.
+                if d.offset.is_none() {
+                    d.offset = gps.and_then(|utc| dates::guess_offset(d.local, utc));
+                }
+                candidates.push((source, d));
+            }
         }
     }
-    // try extracting date from relative_path
-    // TODO: helper binary for checking which paths would decode to what dates
     // TODO[LATER]: add option/button to pre-check date-path patterns on real files tree in GUI
-    for date_path in date_paths {
+    if let Some((_, d)) = match_date_path(relative_path, date_paths).date {
+        candidates.push((DateSource::DatePath, Timestamp::naive(d)));
+    }
+    candidates
+}
+
+/// Outcome of trying `date-path` rules on a path (see `match_date_path`).
+#[derive(Debug, Default, PartialEq)]
+pub struct DatePathMatch {
+    /// Index of the first rule which matched the path and yielded a valid date, and the date.
+    pub date: Option<(usize, NaiveDateTime)>,
+    /// Indexes of rules tried earlier, which matched the path but expanded to an invalid date,
+    /// and their expansions.
+    pub unparsable: Vec<(usize, String)>,
+}
+
+/// Try `date_paths` rules on `relative_path` in order, until one yields a valid date.
+pub fn match_date_path(relative_path: &str, date_paths: &[DatePath]) -> DatePathMatch {
+    const YMD_HMS: &str = "%Y-%m-%d %H:%M:%S";
+    const YMD: &str = "%Y-%m-%d";
+    let mut result = DatePathMatch::default();
+    for (i, date_path) in date_paths.iter().enumerate() {
         if let Some(found) = date_path.path.captures(relative_path) {
             let mut buf = String::new();
             found.expand(&date_path.date, &mut buf);
-            const YMD_HMS: &str = "%Y-%m-%d %H:%M:%S";
-            const YMD: &str = "%Y-%m-%d";
             let date = NaiveDateTime::parse_from_str(&buf, YMD_HMS)
                 .or_else(|_| NaiveDate::parse_from_str(&buf, YMD).map(|d| d.and_hms(0, 0, 0)));
-            if let Ok(d) = date {
-                return Some(d);
+            match date {
+                Ok(d) => {
+                    result.date = Some((i, d));
+                    break;
+                }
+                Err(_) => result.unparsable.push((i, buf)),
             }
         }
     }
-    // TODO[LATER]: try extracting date from file's creation and modification date (NOTE: latter can be earlier than former on Windows!)
-    None
+    result
+}
+
+/// Modification and creation times of a file (if available), in local time.
+// NOTE: on Windows, modification time can be earlier than creation time (e.g. of a copied file).
+pub(crate) fn fs_date_candidates(metadata: &fs::Metadata) -> Vec<Candidate> {
+    let local = |time: SystemTime| Timestamp::from_utc(DateTime::<Utc>::from(time).naive_utc());
+    [
+        (DateSource::Mtime, metadata.modified()),
+        (DateSource::Birthtime, metadata.created()),
+    ]
+    .into_iter()
+    .filter_map(|(source, time)| Some((source, local(time.ok()?))))
+    .collect()
 }
 
 #[cfg(test)]
@@ -313,9 +679,22 @@ mod test {
     use tempfile::tempdir;
 
     use crate::db;
+    use crate::integrity;
 
     use super::*;
 
+    /// Scope of a scan of images with `extensions`, with other settings at their defaults.
+    fn scope_of(extensions: &[String]) -> Scope<'_> {
+        Scope {
+            image_extensions: extensions,
+            video_extensions: &[],
+            ignore_small: None,
+            date_priority: &[],
+            max_date_spread: chrono::Duration::hours(24),
+            fs_dates: false,
+        }
+    }
+
     #[test]
     fn stage2_file_not_found() {
         // arrange
@@ -345,8 +724,7 @@ mod test {
             relative_path,
             &crate::model::FileInfo {
                 hash: hash(&Vec::new()),
-                date: None,
-                thumb: Vec::new(),
+                ..Default::default()
             },
         )
         .unwrap();
@@ -355,7 +733,7 @@ mod test {
 
         // act
 
-        let res = stage2(&tree, &db);
+        let res = stage2(&tree, &db, true);
 
         // assert
 
@@ -365,4 +743,331 @@ mod test {
         assert_eq!(db::exists(&conn, marker, relative_path), Ok(false));
         drop(conn);
     }
+
+    #[test]
+    fn stage1_skips_unchanged_files() {
+        // arrange
+
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("marker.json"), r#"{"id": "foo-marker"}"#).unwrap();
+        let tree = Tree::open(
+            root.path().join("marker.json"),
+            &config::DatePathsPerMarker::new(),
+        )
+        .unwrap();
+        let write_jpeg = |width| {
+            let img = image::DynamicImage::new_rgb8(width, 4);
+            img.save(root.path().join("foo.jpg")).unwrap();
+        };
+        write_jpeg(4);
+        let extensions = config::default_image_extensions();
+        let scope = scope_of(&extensions);
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let db = Arc::new(Mutex::new(conn));
+        let thumbnail = |db: &SyncedDb| -> Vec<u8> {
+            let conn = db.lock().unwrap();
+            conn.query_row(
+                "SELECT thumbnail FROM file
+                    JOIN location ON file.rowid = file_id
+                    WHERE path = 'foo.jpg'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+
+        // act & assert
+
+        stage1(0, &tree, &db, &scope, OnExisting::Skip).unwrap();
+        let conn = db.lock().unwrap();
+        let stat = db::location_stat(&conn, "foo-marker", "foo.jpg").unwrap();
+        assert_eq!(
+            stat.map(|s| s.size),
+            Some(fs::metadata(root.path().join("foo.jpg")).unwrap().len())
+        );
+        // Mark the thumbnail, to detect whether the file gets processed again.
+        conn.execute("UPDATE file SET thumbnail = x'00'", [])
+            .unwrap();
+        drop(conn);
+
+        stage1(0, &tree, &db, &scope, OnExisting::Skip).unwrap();
+        assert_eq!(thumbnail(&db), vec![0]);
+
+        // Changed contents are kept for review, and used once accepted.
+        write_jpeg(8);
+        stage1(0, &tree, &db, &scope, OnExisting::Skip).unwrap();
+        assert_eq!(thumbnail(&db), vec![0]);
+        let incidents = db::open_incidents(&db.lock().unwrap()).unwrap();
+        assert_eq!(incidents.len(), 1);
+        let trees = [tree.clone()];
+        integrity::resolve(&db, &trees, incidents[0].id, integrity::Resolution::Accept).unwrap();
+        stage1(0, &tree, &db, &scope, OnExisting::Skip).unwrap();
+        assert_ne!(thumbnail(&db), vec![0]);
+    }
+
+    #[test]
+    fn scan_records_overwritten_files_as_incidents() {
+        // arrange
+
+        let root = tempdir().unwrap();
+        let marker_path = root.path().join("marker.json");
+        fs::write(&marker_path, r#"{"id": "foo-marker"}"#).unwrap();
+        let original = b"fake jpeg contents";
+        fs::write(root.path().join("foo.jpg"), original).unwrap();
+        let config: Config = toml::from_str("[markers]\ndisk = []\n[date-path]\n").unwrap();
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let db = Arc::new(Mutex::new(conn));
+        process_tree(0, &marker_path, &config, db.clone(), false).unwrap();
+
+        // act
+
+        fs::write(root.path().join("foo.jpg"), b"overwritten by mistake").unwrap();
+        process_tree(0, &marker_path, &config, db.clone(), false).unwrap();
+
+        // assert
+
+        let conn = db.lock().unwrap();
+        let incidents = db::open_incidents(&conn).unwrap();
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].path, "foo.jpg");
+        assert_eq!(incidents[0].expected_hash, hash(original));
+        assert_eq!(incidents[0].actual_hash, hash(b"overwritten by mistake"));
+        assert!(incidents[0].stat_changed);
+        assert_eq!(
+            db::location_hash(&conn, "foo-marker", "foo.jpg").unwrap(),
+            Some(hash(original))
+        );
+    }
+
+    #[test]
+    fn stage1_tracks_other_formats_and_videos() {
+        // arrange
+
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("marker.json"), r#"{"id": "foo-marker"}"#).unwrap();
+        let tree = Tree::open(
+            root.path().join("marker.json"),
+            &config::DatePathsPerMarker::new(),
+        )
+        .unwrap();
+        let png = image::DynamicImage::new_rgba16(6, 4);
+        png.save(root.path().join("screenshot.PNG")).unwrap();
+        fs::write(root.path().join("photo.heic"), b"not really a HEIC").unwrap();
+        fs::write(root.path().join("notes.txt"), b"not an image").unwrap();
+        fs::write(root.path().join("clip.MOV"), b"not really a video").unwrap();
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let db = Arc::new(Mutex::new(conn));
+
+        // act
+
+        let images = config::default_image_extensions();
+        let videos = config::default_video_extensions();
+        let scope = Scope {
+            video_extensions: &videos,
+            ..scope_of(&images)
+        };
+        stage1(0, &tree, &db, &scope, OnExisting::Skip).unwrap();
+
+        // assert
+
+        let conn = db.lock().unwrap();
+        let thumbs: Vec<(String, usize, bool)> = conn
+            .prepare(
+                "SELECT path, length(thumbnail), video FROM location
+                    JOIN file ON file.rowid = file_id
+                    ORDER BY path",
+            )
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(thumbs.len(), 3);
+        // Videos with unreadable headers are still known to be videos.
+        assert_eq!(thumbs[0], ("clip.MOV".to_string(), 0, true));
+        assert_eq!(thumbs[1], ("photo.heic".to_string(), 0, false));
+        assert_eq!(thumbs[2].0, "screenshot.PNG");
+        assert!(thumbs[2].1 > 0);
+        assert!(!thumbs[2].2);
+    }
+
+    #[test]
+    fn stage1_ignores_small_images() {
+        // arrange
+
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("marker.json"), r#"{"id": "foo-marker"}"#).unwrap();
+        let tree = Tree::open(
+            root.path().join("marker.json"),
+            &config::DatePathsPerMarker::new(),
+        )
+        .unwrap();
+        for (name, w, h) in [
+            ("icon.png", 16, 16),
+            ("wide.png", 64, 8),
+            ("big.png", 40, 40),
+        ] {
+            let img = image::DynamicImage::new_rgb8(w, h);
+            img.save(root.path().join(name)).unwrap();
+        }
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let db = Arc::new(Mutex::new(conn));
+
+        // act
+
+        let extensions = config::default_image_extensions();
+        let scope = Scope {
+            ignore_small: Some(config::MinSize { w: 32, h: 32 }),
+            ..scope_of(&extensions)
+        };
+        let skipped = stage1(0, &tree, &db, &scope, OnExisting::Skip).unwrap();
+
+        // assert
+
+        assert_eq!(skipped, 1);
+        let conn = db.lock().unwrap();
+        assert_eq!(db::exists(&conn, "foo-marker", "icon.png"), Ok(false));
+        assert_eq!(db::exists(&conn, "foo-marker", "wide.png"), Ok(true));
+        assert_eq!(db::exists(&conn, "foo-marker", "big.png"), Ok(true));
+    }
+
+    #[test]
+    fn stage1_picks_dates_from_all_locations() {
+        // arrange
+
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("marker.json"), r#"{"id": "foo-marker"}"#).unwrap();
+        let date_paths = config::DatePathsPerMarker::from([(
+            "foo-marker".to_string(),
+            vec![DatePath {
+                date: "$1-$2-01".to_string(),
+                path: regex::Regex::new(r"^(\d{4})/(\d\d)/").unwrap(),
+            }],
+        )]);
+        let tree = Tree::open(root.path().join("marker.json"), &date_paths).unwrap();
+        let img = image::DynamicImage::new_rgb8(4, 4);
+        for dir in ["2021/03", "2020/05"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+            img.save(root.path().join(dir).join("foo.jpg")).unwrap();
+        }
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let db = Arc::new(Mutex::new(conn));
+
+        // act
+
+        let extensions = config::default_image_extensions();
+        let priority = dates::default_priority();
+        let scope = Scope {
+            date_priority: &priority,
+            fs_dates: true,
+            ..scope_of(&extensions)
+        };
+        stage1(0, &tree, &db, &scope, OnExisting::Skip).unwrap();
+
+        // assert
+
+        let conn = db.lock().unwrap();
+        let (date, conflict): (Option<NaiveDateTime>, bool) = conn
+            .query_row(
+                "SELECT date, EXISTS(SELECT 1 FROM file_tag JOIN tag ON tag.rowid = tag_id
+                    WHERE file_id = file.rowid AND tag.name = ?)
+                FROM file",
+                [dates::CONFLICT_TAG],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(date, Some(NaiveDate::from_ymd(2020, 5, 1).and_hms(0, 0, 0)));
+        assert!(conflict);
+        let hash: String = conn
+            .query_row("SELECT hash FROM file", [], |row| row.get(0))
+            .unwrap();
+        let candidates = db::date_candidates_of(&conn, &hash).unwrap();
+        assert_eq!(
+            candidates
+                .iter()
+                .filter(|(_, (source, _))| *source == DateSource::DatePath)
+                .count(),
+            2
+        );
+        assert!(candidates
+            .iter()
+            .any(|(_, (source, _))| *source == DateSource::Mtime));
+    }
+
+    #[test]
+    fn stage1_forgets_fs_dates_when_fallback_disabled() {
+        // arrange
+
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("marker.json"), r#"{"id": "foo-marker"}"#).unwrap();
+        let tree = Tree::open(
+            root.path().join("marker.json"),
+            &config::DatePathsPerMarker::new(),
+        )
+        .unwrap();
+        image::DynamicImage::new_rgb8(4, 4)
+            .save(root.path().join("foo.jpg"))
+            .unwrap();
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let db = Arc::new(Mutex::new(conn));
+        let extensions = config::default_image_extensions();
+        let priority = dates::default_priority();
+        let scope = |fs_dates| Scope {
+            date_priority: &priority,
+            fs_dates,
+            ..scope_of(&extensions)
+        };
+        let date_source = || -> Option<String> {
+            db.lock()
+                .unwrap()
+                .query_row("SELECT date_source FROM file", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        // act & assert
+
+        stage1(0, &tree, &db, &scope(true), OnExisting::Skip).unwrap();
+        assert!(date_source().is_some());
+
+        // Like `scan --deep` after disabling the fallback.
+        stage1(0, &tree, &db, &scope(false), OnExisting::Refresh).unwrap();
+        assert_eq!(date_source(), None);
+        let date: Option<NaiveDateTime> = db
+            .lock()
+            .unwrap()
+            .query_row("SELECT date FROM file", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(date, None);
+    }
+
+    #[test]
+    fn hashes_match_current_and_legacy() {
+        let buf = b"foo";
+        let hashes = Hashes::of(buf);
+        assert_eq!(
+            hashes.current,
+            "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+        );
+        assert_eq!(hashes.legacy, "0beec7b5ea3f0fdbc95d0dd47f3c5bc275da8a33");
+        assert!(hashes.matches(&hashes.legacy) && hash_matches(buf, &hashes.legacy));
+        assert!(hashes.matches(&hashes.current) && hash_matches(buf, &hashes.current));
+        assert!(!hash_matches(b"bar", &hashes.current));
+        let (streamed, size) = Hashes::of_reader(&mut &buf[..]).unwrap();
+        assert_eq!(
+            (streamed.current, streamed.legacy, size),
+            (hashes.current, hashes.legacy, 3)
+        );
+    }
 }
@@ -0,0 +1,389 @@
+//! Cache of thumbnails shown in the gallery, so that they're not loaded from DB, decoded and
+//! uploaded to the GPU again on every frame. Thumbnails are loaded in a background thread - the
+//! visible tiles first, then the ones just outside of the visible part of the gallery, to make
+//! scrolling smooth.
+
+use std::any::TypeId;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use anyhow::Result;
+use iced_native::futures::channel::mpsc as async_mpsc;
+use iced_native::futures::stream::{self, StreamExt};
+use iced_native::image::Handle;
+use iced_native::subscription::{self, Subscription};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, Error::QueryReturnedNoRows};
+
+use crate::db::{self, SyncedDb};
+use crate::imaging;
+use crate::interlude::*;
+
+/// Number of thumbnails kept in memory - enough for a few screens of the gallery.
+pub const CAPACITY: usize = 1000;
+
+/// Number of thumbnails loaded from DB at once, before letting others use it.
+const CHUNK: usize = 20;
+
+#[derive(Clone, Debug)]
+pub struct Thumbnail {
+    pub handle: Handle,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Hash of a file and version of its thumbnail (see `model::THUMBNAIL_VERSION`), so that
+/// regenerated thumbnails aren't served from the cache.
+pub type Key = (String, u32);
+
+/// Thumbnails of files by key. Files without a thumbnail (e.g. videos) are cached as `None`.
+pub struct Thumbnails {
+    cache: Arc<Mutex<Lru>>,
+    /// Channel to the prefetching thread, and the last request sent - the gallery is redrawn
+    /// often without scrolling, e.g. when the cursor moves.
+    prefetch: Mutex<(Sender<Prefetch>, Option<Prefetch>)>,
+    /// Notifications of thumbnails loaded in background, until taken by `loaded`.
+    loaded: Mutex<Option<async_mpsc::UnboundedReceiver<()>>>,
+}
+
+/// Request to load thumbnails of files at offsets from `offset` to `offset + limit`, among files
+/// matching the SQL condition `filter` with `params` bound (in the order of the gallery), and of
+/// the same number of files before and after them.
+#[derive(Clone, PartialEq)]
+struct Prefetch {
+    filter: String,
+    params: Vec<Value>,
+    offset: u32,
+    limit: u32,
+}
+
+impl Thumbnails {
+    /// Create an empty cache, with a background thread loading thumbnails from `db`. The thread
+    /// finishes when the cache is dropped.
+    pub fn new(db: SyncedDb, capacity: usize) -> Self {
+        let cache = Arc::new(Mutex::new(Lru::new(capacity)));
+        let (sender, receiver) = mpsc::channel();
+        let (loaded_sender, loaded) = async_mpsc::unbounded();
+        let worker_cache = Arc::clone(&cache);
+        thread::spawn(move || prefetcher(db, worker_cache, receiver, loaded_sender));
+        Self {
+            cache,
+            prefetch: Mutex::new((sender, None)),
+            loaded: Mutex::new(Some(loaded)),
+        }
+    }
+
+    /// Thumbnails of files with `keys`, or None for the ones not loaded yet (see `prefetch`).
+    pub fn get(&self, keys: &[Key]) -> Vec<Option<Option<Thumbnail>>> {
+        let mut cache = self.cache.lock().unwrap();
+        keys.iter().map(|key| cache.get(key)).collect()
+    }
+
+    /// Load thumbnails of files at offsets from `offset` to `offset + limit` (among files passing
+    /// the `filter`) in background, and then of the same number of files before and after them.
+    pub fn prefetch(&self, filter: &db::Filter, offset: u32, limit: u32) {
+        let request = Prefetch {
+            filter: filter.sql(),
+            params: filter.params(),
+            offset,
+            limit,
+        };
+        let (sender, last) = &mut *self.prefetch.lock().unwrap();
+        if last.as_ref() != Some(&request) {
+            // The thread runs as long as the cache exists, so sending can't fail.
+            let _ = sender.send(request.clone());
+            *last = Some(request);
+        }
+    }
+
+    /// Events emitted whenever thumbnails were loaded in background, so that the gallery can be
+    /// redrawn with them. Only the first subscription gets the events, and it runs as long as it's
+    /// returned from `Application::subscription`.
+    pub fn loaded(&self) -> Subscription<()> {
+        let loaded = self.loaded.lock().unwrap().take();
+        subscription::run(TypeId::of::<Self>(), stream::iter(loaded).flatten())
+    }
+}
+
+/// Key of a file, its thumbnail as stored in DB, and the dimensions of the thumbnail.
+type Loaded = (Key, Vec<u8>, Option<(u32, u32)>);
+
+/// Load thumbnails of files with given hashes, with their dimensions (None if the thumbnail is
+/// missing or not a valid JPEG). Dimensions are read from the JPEG header if not yet in DB. Files
+/// no longer in DB are skipped.
+fn load(db: &Connection, hashes: &[String]) -> Result<Vec<Loaded>> {
+    let mut query = db.prepare_cached(
+        "SELECT thumbnail, thumb_version, thumb_width, thumb_height FROM file WHERE hash = ?",
+    )?;
+    let mut thumbnails = Vec::new();
+    for hash in hashes {
+        let row = query.query_row(params![hash], |row| {
+            let thumb: Option<Vec<u8>> = row.get(0)?;
+            Ok((
+                thumb.unwrap_or_default(),
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+            ))
+        });
+        let (thumb, version, width, height) = match row {
+            Ok(row) => row,
+            Err(QueryReturnedNoRows) => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let size = match (width, height) {
+            (Some(width), Some(height)) => Some((width, height)),
+            _ => imaging::jpeg_dimensions(&thumb),
+        };
+        thumbnails.push(((hash.clone(), version), thumb, size));
+    }
+    Ok(thumbnails)
+}
+
+fn prefetcher(
+    db: SyncedDb,
+    cache: Arc<Mutex<Lru>>,
+    requests: Receiver<Prefetch>,
+    loaded: async_mpsc::UnboundedSender<()>,
+) {
+    while let Ok(mut request) = requests.recv() {
+        // Only the latest request matters, the earlier ones are for places already scrolled by.
+        while let Ok(newer) = requests.try_recv() {
+            request = newer;
+        }
+        if let Err(err) = prefetch(&db, &cache, &request, &loaded) {
+            ieprintln!("Error prefetching thumbnails: " error_chain(&err) ".");
+        }
+    }
+}
+
+fn prefetch(
+    db: &SyncedDb,
+    cache: &Mutex<Lru>,
+    request: &Prefetch,
+    loaded: &async_mpsc::UnboundedSender<()>,
+) -> Result<()> {
+    let start = request.offset.saturating_sub(request.limit);
+    let mut keys = db
+        .lock()
+        .unwrap()
+        .prepare_cached(&ifmt!(
+            "SELECT hash, thumb_version FROM file WHERE " request.filter "
+                ORDER BY " db::FILE_ORDER "
+                LIMIT ? OFFSET ?"
+        ))?
+        .query_map(
+            params_from_iter(
+                request
+                    .params
+                    .iter()
+                    .cloned()
+                    .chain([(3 * request.limit).into(), start.into()]),
+            ),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?
+        .collect::<rusqlite::Result<Vec<Key>>>()?;
+    // Visible tiles first, then the ones below and above them.
+    let visible = ((request.offset - start) as usize).min(keys.len());
+    keys.rotate_left(visible);
+    let missing: Vec<_> = {
+        let mut cache = cache.lock().unwrap();
+        keys.into_iter()
+            .filter(|key| cache.get(key).is_none())
+            .map(|(hash, _)| hash)
+            .collect()
+    };
+
+    // The DB is shared with the gallery and the scan, so it's locked only for a chunk at a time.
+    for chunk in missing.chunks(CHUNK) {
+        let thumbnails = load(&db.lock().unwrap(), chunk)?;
+        // Decode here, so that the renderer gets ready pixels.
+        for (key, thumb, size) in thumbnails {
+            let thumbnail = size
+                .and_then(|_| image::load_from_memory(&thumb).ok())
+                .map(|img| {
+                    let img = img.to_bgra8();
+                    let (width, height) = img.dimensions();
+                    Thumbnail {
+                        handle: Handle::from_pixels(width, height, img.into_raw()),
+                        width,
+                        height,
+                    }
+                });
+            cache.lock().unwrap().insert(key, thumbnail);
+        }
+        // Nobody listens in tests.
+        let _ = loaded.unbounded_send(());
+    }
+    Ok(())
+}
+
+/// Least recently used entries are evicted when over capacity.
+struct Lru {
+    capacity: usize,
+    entries: HashMap<Key, (Option<Thumbnail>, u64)>,
+    /// Keys of entries by the time of their last use.
+    order: BTreeMap<u64, Key>,
+    time: u64,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            time: 0,
+        }
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Option<Thumbnail>> {
+        self.time += 1;
+        let (thumbnail, used) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        *used = self.time;
+        self.order.insert(self.time, key.clone());
+        Some(thumbnail.clone())
+    }
+
+    fn insert(&mut self, key: Key, thumbnail: Option<Thumbnail>) {
+        self.time += 1;
+        if let Some((_, used)) = self.entries.insert(key.clone(), (thumbnail, self.time)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.time, key);
+        while self.entries.len() > self.capacity {
+            let oldest = match self.order.keys().next() {
+                Some(&used) => self.order.remove(&used).unwrap(),
+                None => break,
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, ImageOutputFormat};
+
+    use crate::model::{FileInfo, THUMBNAIL_VERSION};
+
+    use super::*;
+
+    fn key(hash: &str) -> Key {
+        (hash.to_string(), THUMBNAIL_VERSION)
+    }
+
+    #[test]
+    fn load_thumbnails_with_dimensions() {
+        let conn = Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let mut thumb = Vec::new();
+        DynamicImage::new_rgb8(40, 30)
+            .write_to(&mut thumb, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        for (hash, thumb) in [("hash-a", thumb.clone()), ("hash-b", Vec::new())] {
+            let info = FileInfo {
+                hash: hash.to_string(),
+                thumb,
+                duration_ms: Some(1000),
+                ..Default::default()
+            };
+            db::upsert(&conn, "disk", hash, &info).unwrap();
+        }
+        let stored: (u32, u32) = conn
+            .query_row(
+                "SELECT thumb_width, thumb_height FROM file WHERE hash = 'hash-a'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(stored, (40, 30));
+
+        // Thumbnails stored before their dimensions were, get measured.
+        conn.execute(
+            "UPDATE file SET thumb_width = NULL, thumb_height = NULL",
+            [],
+        )
+        .unwrap();
+        let hashes = ["hash-a", "hash-b", "hash-gone"].map(String::from);
+        let loaded = load(&conn, &hashes).unwrap();
+        assert_eq!(
+            loaded,
+            vec![
+                (key("hash-a"), thumb, Some((40, 30))),
+                (key("hash-b"), Vec::new(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefetch_visible_and_nearby_thumbnails() {
+        // arrange
+
+        let conn = Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let mut thumb = Vec::new();
+        DynamicImage::new_rgb8(4, 3)
+            .write_to(&mut thumb, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        for i in 0..100 {
+            let info = FileInfo {
+                hash: format!("hash-{:02}", i),
+                thumb: thumb.clone(),
+                ..Default::default()
+            };
+            db::upsert(&conn, "disk", &info.hash, &info).unwrap();
+        }
+        let db = Arc::new(Mutex::new(conn));
+        let cache = Mutex::new(Lru::new(CAPACITY));
+        let (sender, mut receiver) = async_mpsc::unbounded();
+        let request = Prefetch {
+            filter: "1".to_string(),
+            params: Vec::new(),
+            offset: 50,
+            limit: 10,
+        };
+
+        // act
+
+        prefetch(&db, &cache, &request, &sender).unwrap();
+
+        // assert
+
+        let mut cache = cache.lock().unwrap();
+        assert_eq!(cache.entries.len(), 30);
+        assert!(matches!(cache.get(&key("hash-40")), Some(Some(_))));
+        assert!(matches!(cache.get(&key("hash-69")), Some(Some(_))));
+        assert!(cache.get(&key("hash-70")).is_none());
+        // Thumbnails of other versions aren't served.
+        assert!(cache.get(&("hash-50".to_string(), 0)).is_none());
+        // Each chunk loaded is announced.
+        let mut events = 0;
+        while let Ok(Some(())) = receiver.try_next() {
+            events += 1;
+        }
+        assert_eq!(events, 2);
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let thumbnail = || Thumbnail {
+            handle: Handle::from_pixels(1, 1, vec![0; 4]),
+            width: 1,
+            height: 1,
+        };
+        let mut lru = Lru::new(2);
+        lru.insert(key("a"), Some(thumbnail()));
+        lru.insert(key("b"), None);
+        assert!(lru.get(&key("a")).is_some());
+        lru.insert(key("c"), Some(thumbnail()));
+
+        assert!(lru.get(&key("b")).is_none());
+        assert!(matches!(lru.get(&key("a")), Some(Some(_))));
+        assert!(matches!(lru.get(&key("c")), Some(Some(_))));
+        assert_eq!(lru.entries.len(), 2);
+        assert_eq!(lru.order.len(), 2);
+    }
+}
@@ -0,0 +1,196 @@
+//! Reading metadata of video files in ISO base media format (MP4, MOV, 3GP).
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use anyhow::{bail, Result};
+use chrono::NaiveDateTime;
+
+/// Seconds between 1904-01-01 (epoch of MP4 timestamps) and 1970-01-01.
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct VideoInfo {
+    /// Creation time from the `mvhd` atom. Note: most cameras store it in UTC.
+    pub created: Option<NaiveDateTime>,
+    pub duration_ms: Option<u64>,
+}
+
+/// Read creation time and duration from the movie header (`moov/mvhd`) of a video file.
+pub fn read_info(r: &mut (impl Read + Seek)) -> Result<VideoInfo> {
+    let end = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(0))?;
+    let moov_end = match find_box(r, *b"moov", end)? {
+        Some(moov_end) => moov_end,
+        None => bail!("no 'moov' box found"),
+    };
+    if find_box(r, *b"mvhd", moov_end)?.is_none() {
+        bail!("no 'mvhd' box found in 'moov'");
+    }
+
+    let mut version_flags = [0u8; 4];
+    r.read_exact(&mut version_flags)?;
+    let (created, timescale, duration) = match version_flags[0] {
+        0 => {
+            let created = read_u32(r)? as u64;
+            let _modified = read_u32(r)?;
+            let timescale = read_u32(r)?;
+            let duration = read_u32(r)? as u64;
+            (created, timescale, duration)
+        }
+        1 => {
+            let created = read_u64(r)?;
+            let _modified = read_u64(r)?;
+            let timescale = read_u32(r)?;
+            let duration = read_u64(r)?;
+            (created, timescale, duration)
+        }
+        v => bail!("unknown 'mvhd' version {}", v),
+    };
+
+    // Zero means the time was not set.
+    let created = match created {
+        0 => None,
+        secs => i64::try_from(secs)
+            .ok()
+            .and_then(|secs| secs.checked_sub(MP4_EPOCH_OFFSET))
+            .and_then(|secs| NaiveDateTime::from_timestamp_opt(secs, 0)),
+    };
+    // Computed in u128, as a corrupt 64-bit duration could overflow.
+    let duration_ms = match timescale {
+        0 => None,
+        _ => u64::try_from(duration as u128 * 1000 / timescale as u128).ok(),
+    };
+    Ok(VideoInfo {
+        created,
+        duration_ms,
+    })
+}
+
+/// Scan boxes from current position up to `end`, looking for one of type `wanted`. If found,
+/// leaves the reader at the start of the box's contents and returns the position of its end.
+fn find_box(r: &mut (impl Read + Seek), wanted: [u8; 4], end: u64) -> Result<Option<u64>> {
+    let mut pos = r.stream_position()?;
+    while pos.saturating_add(8) <= end {
+        let size = read_u32(r)? as u64;
+        let mut kind = [0u8; 4];
+        r.read_exact(&mut kind)?;
+        let (header, size) = match size {
+            0 => (8, end - pos),
+            1 => (16, read_u64(r)?),
+            size => (8, size),
+        };
+        if size < header {
+            bail!(
+                "invalid size {} of box {:?}",
+                size,
+                String::from_utf8_lossy(&kind)
+            );
+        }
+        let box_end = match pos.checked_add(size) {
+            Some(box_end) => box_end,
+            None => bail!(
+                "invalid size {} of box {:?}",
+                size,
+                String::from_utf8_lossy(&kind)
+            ),
+        };
+        if kind == wanted {
+            return Ok(Some(box_end));
+        }
+        pos = box_end;
+        r.seek(SeekFrom::Start(pos))?;
+    }
+    Ok(None)
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Format duration as `m:ss`, or `h:mm:ss` for longer videos.
+pub fn format_duration(duration_ms: u64) -> String {
+    let secs = duration_ms / 1000;
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut buf = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+        buf.extend_from_slice(kind);
+        buf.extend_from_slice(contents);
+        buf
+    }
+
+    #[test]
+    fn read_mvhd() {
+        let created = NaiveDate::from_ymd(2021, 7, 4).and_hms(18, 30, 5);
+        let mut mvhd = vec![0u8; 4]; // version 0, no flags
+        mvhd.extend(((created.timestamp() + MP4_EPOCH_OFFSET) as u32).to_be_bytes());
+        mvhd.extend(0u32.to_be_bytes()); // modification time
+        mvhd.extend(600u32.to_be_bytes()); // timescale
+        mvhd.extend(45_300u32.to_be_bytes()); // duration, in timescale units
+        mvhd.extend([0u8; 80]); // rest of the header, irrelevant here
+
+        let mut file = mp4_box(b"ftyp", b"qt  \0\0\0\0qt  ");
+        file.extend(mp4_box(b"mdat", &[0xAB; 100]));
+        let mut moov = mp4_box(b"udta", b"");
+        moov.extend(mp4_box(b"mvhd", &mvhd));
+        file.extend(mp4_box(b"moov", &moov));
+
+        let info = read_info(&mut io::Cursor::new(file)).unwrap();
+        assert_eq!(
+            info,
+            VideoInfo {
+                created: Some(created),
+                duration_ms: Some(75_500),
+            }
+        );
+        assert_eq!(format_duration(75_500), "1:15");
+        assert_eq!(format_duration(3_723_000), "1:02:03");
+    }
+
+    #[test]
+    fn corrupt_sizes() {
+        let mut mvhd = vec![1u8, 0, 0, 0]; // version 1, no flags
+        mvhd.extend(u64::MAX.to_be_bytes()); // creation time
+        mvhd.extend(0u64.to_be_bytes()); // modification time
+        mvhd.extend(1u32.to_be_bytes()); // timescale
+        mvhd.extend(u64::MAX.to_be_bytes()); // duration
+        let file = mp4_box(b"moov", &mp4_box(b"mvhd", &mvhd));
+        assert_eq!(
+            read_info(&mut io::Cursor::new(file)).unwrap(),
+            VideoInfo::default()
+        );
+
+        // Box with a 64-bit size reaching past the end of any file.
+        let mut file = 1u32.to_be_bytes().to_vec();
+        file.extend(b"mdat");
+        file.extend(u64::MAX.to_be_bytes());
+        file.extend([0u8; 8]);
+        assert!(read_info(&mut io::Cursor::new(file)).is_err());
+    }
+
+    #[test]
+    fn missing_moov() {
+        let file = mp4_box(b"ftyp", b"isom");
+        assert!(read_info(&mut io::Cursor::new(file)).is_err());
+    }
+}
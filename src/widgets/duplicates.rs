@@ -0,0 +1,98 @@
+use iced::pure::{button, checkbox, column, image, row, scrollable, text, Element};
+use iced_native::image::Handle;
+
+use crate::coverage::human_bytes;
+use crate::duplicates::Entry;
+
+/// Clusters of near-duplicate images, shown side by side so that lesser copies can be marked.
+pub struct Duplicates {
+    clusters: Vec<Vec<(Entry, Handle)>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Set whether `n`-th entry in `i`-th cluster is marked as discarded.
+    SetDiscarded(usize, usize, bool),
+    Close,
+}
+
+impl Duplicates {
+    pub fn new(clusters: Vec<Vec<Entry>>) -> Self {
+        let clusters = clusters
+            .into_iter()
+            .map(|cluster| {
+                cluster
+                    .into_iter()
+                    .map(|entry| {
+                        let handle = Handle::from_memory(entry.thumb.clone());
+                        (entry, handle)
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { clusters }
+    }
+
+    pub fn get(&self, i: usize, n: usize) -> Option<&Entry> {
+        self.clusters.get(i)?.get(n).map(|(entry, _)| entry)
+    }
+
+    pub fn update(&mut self, event: Event) {
+        if let Event::SetDiscarded(i, n, discarded) = event {
+            if let Some((entry, _)) = self.clusters.get_mut(i).and_then(|c| c.get_mut(n)) {
+                entry.discarded = discarded;
+            }
+        }
+    }
+
+    pub fn view(&self) -> Element<'_, Event> {
+        let header = row()
+            .spacing(20)
+            .push(button(text("Back")).on_press(Event::Close).padding(10))
+            .push(text(format!(
+                "{} group(s) of similar images",
+                self.clusters.len()
+            )));
+        let clusters =
+            self.clusters
+                .iter()
+                .enumerate()
+                .fold(column().spacing(40), |col, (i, cluster)| {
+                    col.push(
+                        cluster
+                            .iter()
+                            .enumerate()
+                            .fold(row().spacing(20), |row, (n, (entry, handle))| {
+                                row.push(tile(entry, handle, i, n))
+                            }),
+                    )
+                });
+        column()
+            .spacing(20)
+            .padding(20)
+            .push(header)
+            .push(scrollable(clusters))
+            .into()
+    }
+}
+
+fn tile<'a>(entry: &'a Entry, handle: &Handle, i: usize, n: usize) -> Element<'a, Event> {
+    let resolution = match (entry.width, entry.height) {
+        (Some(w), Some(h)) => format!("{} x {}", w, h),
+        _ => "unknown resolution".to_string(),
+    };
+    let size = match entry.size {
+        Some(size) => human_bytes(size),
+        None => "unknown size".to_string(),
+    };
+    column()
+        .spacing(5)
+        .width(iced::Length::Units(200))
+        .push(image(handle.clone()))
+        .push(text(resolution))
+        .push(text(size))
+        .push(checkbox("Discard", entry.discarded, move |discarded| {
+            Event::SetDiscarded(i, n, discarded)
+        }))
+        .into()
+}
@@ -1,5 +1,8 @@
+use std::cell::RefCell;
 use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
 
+use chrono::NaiveDateTime;
 use iced::pure::{Element, Widget};
 use iced_graphics::{Color, Rectangle};
 use iced_native::alignment;
@@ -9,20 +12,25 @@ use iced_native::renderer::{self, Quad};
 use iced_native::text::{self, Text};
 use iced_native::{layout, Clipboard, Layout, Length, Point, Shell, Size};
 use iced_pure::widget::tree::{self, Tree};
-use image::ImageDecoder;
 use itertools::Itertools;
-use rusqlite::params;
 
+use crate::dates::DateSource;
+use crate::db::{self, Filter};
 use crate::interlude::*;
+use crate::thumbnails::{Key, Thumbnail, Thumbnails};
+use crate::video;
 
 pub struct Gallery<Message> {
     pub db: Arc<Mutex<rusqlite::Connection>>,
     pub selection: Selection,
+    pub filter: Filter,
+    thumbnails: Arc<Thumbnails>,
 
     tile_w: f32,
     tile_h: f32,
     spacing: f32,
     on_select: Option<Box<dyn Fn(Selection) -> Message>>,
+    on_open: Option<Box<dyn Fn(u32) -> Message>>,
 }
 
 #[derive(Copy, Clone, Default, Debug)]
@@ -51,9 +59,39 @@ impl Selection {
     }
 }
 
+/// File shown on a tile of the gallery.
+struct Tile {
+    /// Key of the thumbnail of the file.
+    key: Key,
+    date: Option<NaiveDateTime>,
+    duration_ms: Option<u64>,
+    video: bool,
+    /// Whether the date was taken from filesystem timestamps, so is only approximate.
+    low_confidence: bool,
+}
+
+/// Maximum delay between two clicks on the same tile for them to be treated as a double-click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(500);
+
+/// Files on the tiles drawn last, so that they're not queried from DB again on every frame. They
+/// are valid as long as the filter, the range of tiles and the contents of DB stay the same.
+struct Page {
+    filter: Filter,
+    offset: u32,
+    limit: u32,
+    revision: u64,
+    tiles: Vec<Tile>,
+    /// Offset of the file hovered last, and its locations shown in the tooltip.
+    hovered: Option<(u32, String)>,
+}
+
 #[derive(Default)]
 struct InternalState {
     selecting: bool,
+    /// Time & index of last clicked tile, for detecting double-clicks.
+    last_click: Option<(Instant, u32)>,
+    /// Drawing gets the state only by shared reference.
+    page: RefCell<Option<Page>>,
 }
 
 impl<'a> From<&'a mut Tree> for &'a mut InternalState {
@@ -69,15 +107,18 @@ impl<'a> From<&'a Tree> for &'a InternalState {
 }
 
 impl<Message> Gallery<Message> {
-    pub fn new(db: Arc<Mutex<rusqlite::Connection>>) -> Self {
+    pub fn new(db: Arc<Mutex<rusqlite::Connection>>, thumbnails: Arc<Thumbnails>) -> Self {
         Self {
             db,
             selection: Default::default(),
+            filter: Default::default(),
+            thumbnails,
 
             tile_w: 200.0,
             tile_h: 200.0,
             spacing: 25.0,
             on_select: None,
+            on_open: None,
         }
     }
 
@@ -86,19 +127,42 @@ impl<Message> Gallery<Message> {
         self
     }
 
+    pub fn with_filter(mut self, f: Filter) -> Self {
+        self.filter = f;
+        self
+    }
+
     pub fn on_select(mut self, f: impl Fn(Selection) -> Message + 'static) -> Self {
         self.on_select = Some(Box::new(f));
         self
     }
 
+    /// Set message to emit when a tile is double-clicked.
+    pub fn on_open(mut self, f: impl Fn(u32) -> Message + 'static) -> Self {
+        self.on_open = Some(Box::new(f));
+        self
+    }
+
     fn columns(&self, layout: &Layout) -> u32 {
         ((layout.bounds().width - self.spacing) / (self.tile_w + self.spacing)) as u32
     }
 
-    fn xy_to_offset(&self, layout: &Layout, p: Point) -> Option<u32> {
-        // Note: all calculations in "full" layout coordinates, not in a virtual viewport window.
+    /// Function computing the y coordinate of the top of the row with the file at `offset`, given
+    /// the size of the gallery - for scrolling to the file.
+    pub fn row_y(&self, offset: u32) -> impl Fn(Size) -> f32 {
+        let (tile_w, tile_h, spacing) = (self.tile_w, self.tile_h, self.spacing);
+        move |size| {
+            let columns = (((size.width - spacing) / (tile_w + spacing)) as u32).max(1);
+            (offset / columns) as f32 * (tile_h + spacing)
+        }
+    }
 
-        if p.x < 0.0 || p.y < 0.0 {
+    fn xy_to_offset(&self, layout: &Layout, p: Point) -> Option<u32> {
+        // Note: all calculations in "full" layout coordinates, not in a virtual viewport window,
+        // relative to the top-left corner of the gallery.
+        let bounds = layout.bounds();
+        let p = Point::new(p.x - bounds.x, p.y - bounds.y);
+        if p.x < 0.0 || p.y < 0.0 || p.x >= bounds.width {
             return None;
         }
 
@@ -113,6 +177,42 @@ impl<Message> Gallery<Message> {
         Some(row * self.columns(layout) + col)
     }
 
+    /// Files on the tiles at offsets from `offset` to `offset + limit`.
+    fn load_tiles(&self, offset: u32, limit: u32) -> Vec<Tile> {
+        let db = self.db.lock().unwrap();
+        // TODO[LATER]: think whether to remove .unwrap()
+        let mut query = db
+            .prepare_cached(&ifmt!(
+                r"SELECT hash, thumb_version, date, duration_ms, video, date_source
+                    FROM file
+                    WHERE " self.filter.sql() "
+                    ORDER BY " db::FILE_ORDER "
+                    LIMIT ? OFFSET ?"
+            ))
+            .unwrap();
+        let tiles = query
+            .query_map(
+                db::with_params(&self.filter, [limit.into(), offset.into()]),
+                |row| {
+                    let date_source: Option<String> = row.get_unwrap(5);
+                    let low_confidence = date_source
+                        .and_then(|s| DateSource::from_name(&s))
+                        .map_or(false, DateSource::is_filesystem);
+                    Ok(Tile {
+                        key: (row.get_unwrap(0), row.get_unwrap(1)),
+                        date: row.get_unwrap(2),
+                        duration_ms: row.get_unwrap(3),
+                        video: row.get_unwrap(4),
+                        low_confidence,
+                    })
+                },
+            )
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        tiles
+    }
+
     fn offset_selected(&self, offset: u32) -> bool {
         self.selection.range().contains(&offset)
     }
@@ -143,9 +243,7 @@ where
         // println!("MCDBG Gallery::layout(limits: {:?})", limits);
 
         let db = self.db.lock().unwrap();
-        let n_files: u32 = db
-            .query_row("SELECT COUNT(*) FROM file", [], |row| row.get(0))
-            .unwrap();
+        let n_files = db::count_files(&db, &self.filter).unwrap();
         drop(db);
 
         let width = limits.max().width;
@@ -160,7 +258,7 @@ where
 
     fn draw(
         &self,
-        _tree: &Tree,
+        tree: &Tree,
         renderer: &mut Renderer,
         _style: &renderer::Style,
         layout: Layout<'_>,
@@ -180,41 +278,45 @@ where
         //  hecrj: Yes, same system.
 
         let columns = self.columns(&layout);
+        let bounds = layout.bounds();
 
         // Index of first thumbnail to draw in top-left corner
         let offset = self
-            .xy_to_offset(&layout, Point::new(0., viewport.y))
+            .xy_to_offset(&layout, Point::new(bounds.x, viewport.y))
             .unwrap_or(0);
         let limit = (2 + (viewport.height / (self.tile_h + self.spacing)) as u32) * columns;
 
-        let db = self.db.lock().unwrap();
-
         // FIXME: calculate LIMIT & OFFSET based on viewport vs. layout.bounds
-        // TODO[LATER]: think whether to remove .unwrap()
-        let mut query = db
-            .prepare_cached(
-                r"SELECT hash, date, thumbnail
-                    FROM file
-                    ORDER BY date
-                    LIMIT ? OFFSET ?",
-            )
-            .unwrap();
-        let file_iter = query
-            .query_map(params!(limit, offset), |row| {
-                Ok(crate::model::FileInfo {
-                    hash: row.get_unwrap(0),
-                    date: row.get_unwrap(1),
-                    thumb: row.get_unwrap(2),
-                })
-            })
-            .unwrap();
+        let state: &InternalState = tree.into();
+        let mut page = state.page.borrow_mut();
+        let revision = db::revision();
+        let stale = !matches!(&*page, Some(page) if page.filter == self.filter
+            && (page.offset, page.limit, page.revision) == (offset, limit, revision));
+        if stale {
+            *page = Some(Page {
+                filter: self.filter.clone(),
+                offset,
+                limit,
+                revision,
+                tiles: self.load_tiles(offset, limit),
+                hovered: None,
+            });
+        }
+        let page = page.as_mut().unwrap();
+        let files = &page.tiles;
+        let keys: Vec<_> = files.iter().map(|file| file.key.clone()).collect();
+        let thumbnails = self.thumbnails.get(&keys);
+        // Thumbnails not cached yet are loaded in background, followed by the screens above and
+        // below, for when the user scrolls there.
+        self.thumbnails.prefetch(&self.filter, offset, limit);
 
         // println!("{:?} {:?}", layout.bounds(), &viewport);
 
         let mut last_date = String::new();
-        let mut x = self.spacing;
-        let mut y = self.spacing + (offset / columns) as f32 * (self.tile_h + self.spacing);
-        for (i, row) in file_iter.enumerate() {
+        let mut x = bounds.x + self.spacing;
+        let mut y =
+            bounds.y + self.spacing + (offset / columns) as f32 * (self.tile_h + self.spacing);
+        for (i, (file, thumbnail)) in files.iter().zip(thumbnails).enumerate() {
             // Mark tile as selected when appropriate.
             if self.offset_selected(offset + i as u32) {
                 renderer.fill_quad(
@@ -233,32 +335,108 @@ where
                 );
             }
 
-            let file = row.unwrap();
-
-            // Extract dimensions of thumbnail
-            let (w, h) = image::jpeg::JpegDecoder::new(std::io::Cursor::new(&file.thumb))
-                .unwrap()
-                .dimensions();
-            let (w, h) = (w as f32, h as f32);
-            // Calculate scale, keeping aspect ratio
-            let scale = 1_f32.min((w / self.tile_w).max(h / self.tile_h));
-            // Calculate alignment so that the thumbnail is centered in its space
-            let align_x = (self.tile_w - w / scale) / 2.0;
-            let align_y = (self.tile_h - h / scale) / 2.0;
-
-            renderer.draw(
-                iced_image::Handle::from_memory(file.thumb),
-                Rectangle {
-                    x: x + align_x,
-                    y: y + align_y,
-                    width: w,
-                    height: h,
-                },
-            );
+            // Videos, and files in formats we can't decode, have no thumbnail. Thumbnails not
+            // loaded yet are drawn as placeholders too.
+            match thumbnail {
+                Some(Some(Thumbnail {
+                    handle,
+                    width,
+                    height,
+                })) => {
+                    let (w, h) = (width as f32, height as f32);
+                    // Calculate scale, keeping aspect ratio
+                    let scale = 1_f32.min((w / self.tile_w).max(h / self.tile_h));
+                    // Calculate alignment so that the thumbnail is centered in its space
+                    let align_x = (self.tile_w - w / scale) / 2.0;
+                    let align_y = (self.tile_h - h / scale) / 2.0;
+
+                    renderer.draw(
+                        handle,
+                        Rectangle {
+                            x: x + align_x,
+                            y: y + align_y,
+                            width: w,
+                            height: h,
+                        },
+                    );
+                }
+                thumbnail => {
+                    let bounds = Rectangle {
+                        x,
+                        y,
+                        width: self.tile_w,
+                        height: self.tile_h,
+                    };
+                    renderer.fill_quad(
+                        Quad {
+                            bounds,
+                            border_radius: 0.,
+                            border_width: 0.,
+                            border_color: Color::WHITE,
+                        },
+                        Color::from_rgb(0.8, 0.8, 0.8),
+                    );
+                    renderer.fill_text(Text {
+                        content: match thumbnail {
+                            None => "Loading...",
+                            Some(_) if file.video => "Video",
+                            Some(_) => "No preview",
+                        },
+                        bounds: Rectangle {
+                            x: bounds.center_x(),
+                            y: bounds.center_y(),
+                            ..bounds
+                        },
+                        color: Color::BLACK,
+                        size: 16.0,
+                        font: iced_native::Font::Default,
+                        horizontal_alignment: alignment::Horizontal::Center,
+                        vertical_alignment: alignment::Vertical::Center,
+                    });
+                }
+            }
+
+            // Show a badge with duration over videos.
+            if file.video {
+                let badge = Rectangle {
+                    x,
+                    y: y + self.tile_h - 20.0,
+                    width: 60.0,
+                    height: 20.0,
+                };
+                renderer.fill_quad(
+                    Quad {
+                        bounds: badge,
+                        border_radius: 3.,
+                        border_width: 0.,
+                        border_color: Color::WHITE,
+                    },
+                    Color::from_rgba(0., 0., 0., 0.7),
+                );
+                let label = match file.duration_ms {
+                    Some(duration_ms) => video::format_duration(duration_ms),
+                    None => "?:??".to_string(),
+                };
+                renderer.fill_text(Text {
+                    content: &label,
+                    bounds: Rectangle {
+                        x: badge.center_x(),
+                        y: badge.center_y(),
+                        ..badge
+                    },
+                    color: Color::WHITE,
+                    size: 14.0,
+                    font: iced_native::Font::Default,
+                    horizontal_alignment: alignment::Horizontal::Center,
+                    vertical_alignment: alignment::Vertical::Center,
+                });
+            }
 
             // Display date header if necessary
             // TODO[LATER]: start 1 row earlier to make sure date is not displayed too greedily
+            // Dates taken from filesystem timestamps are only approximate.
             let date = match file.date {
+                Some(d) if file.low_confidence => d.format("~%Y-%m-%d").to_string(),
                 Some(d) => d.format("%Y-%m-%d").to_string(),
                 None => "Unknown date".to_owned(),
             };
@@ -282,8 +460,8 @@ where
 
             // Calculate x and y for next image
             x += self.tile_w + self.spacing;
-            if x + self.tile_w > viewport.width {
-                x = self.spacing;
+            if x + self.tile_w > bounds.x + bounds.width {
+                x = bounds.x + self.spacing;
                 y += self.tile_h + self.spacing;
                 if y >= viewport.y + viewport.height {
                     break;
@@ -295,25 +473,16 @@ where
         // println!("cursor: {:?}", cursor);
         if let Some(hovered_offset) = self.xy_to_offset(&layout, cursor) {
             // println!("hovered_offset: {:?}", hovered_offset);
-            let locations = db
-                .prepare_cached(
-                    r"SELECT backend_tag, path
-                        FROM location
-                        WHERE file_id = (SELECT rowid
-                            FROM file
-                            ORDER BY date
-                            LIMIT 1 OFFSET ?)
-                        ORDER BY backend_tag ASC, path ASC",
-                )
-                .unwrap()
-                .query_map([hovered_offset], |row| {
-                    let backend: String = row.get_unwrap(0);
-                    let path: String = row.get_unwrap(1);
-                    Ok(backend + ": " + path.as_str())
-                })
-                .unwrap()
-                .map(|x: Result<String, _>| x.unwrap())
-                .join("\n");
+            if !matches!(&page.hovered, Some((offset, _)) if *offset == hovered_offset) {
+                let db = self.db.lock().unwrap();
+                let locations = db::locations(&db, &self.filter, hovered_offset)
+                    .unwrap()
+                    .into_iter()
+                    .map(|(backend, path)| backend + ": " + path.as_str())
+                    .join("\n");
+                page.hovered = Some((hovered_offset, locations));
+            }
+            let locations = &page.hovered.as_ref().unwrap().1;
             let text = {
                 let content = locations.as_str();
                 let size = 12u16;
@@ -360,6 +529,13 @@ where
         match event {
             Event::Mouse(ButtonPressed(Button::Left)) => {
                 if let Some(i) = self.xy_to_offset(&layout, cursor_position) {
+                    let now = Instant::now();
+                    if let (Some(on_open), Some((t, last))) = (&self.on_open, state.last_click) {
+                        if last == i && now.duration_since(t) < DOUBLE_CLICK {
+                            shell.publish(on_open(i));
+                        }
+                    }
+                    state.last_click = Some((now, i));
                     self.selection = Selection::single(i);
                     state.selecting = true;
                     // println!("PRESS: {:?} i={}", cursor_position, i);
@@ -0,0 +1,42 @@
+use iced::pure::{column, text, Element};
+
+use crate::coverage::human_bytes;
+use crate::model::FileDetails;
+
+/// Metadata of a single file selected in the gallery. Unknown values are skipped.
+pub fn view<'a, Message: 'a>(details: &FileDetails) -> Element<'a, Message> {
+    let camera = &details.camera;
+    let lines = [
+        details
+            .date
+            .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string()),
+        details.place.clone(),
+        match (details.width, details.height) {
+            (Some(w), Some(h)) => Some(format!(
+                "{} x {} ({:.1} MP)",
+                w,
+                h,
+                w as f64 * h as f64 / 1e6
+            )),
+            _ => None,
+        },
+        details.size.map(human_bytes),
+        details
+            .duration_ms
+            .map(|ms| format!("{}:{:02}", ms / 60_000, ms / 1000 % 60)),
+        camera.name(),
+        camera.lens.clone(),
+        camera.focal_length.map(|f| format!("{} mm", f)),
+        camera.exposure.map(|e| match e {
+            e if e > 0. && e < 1. => format!("1/{} s", (1. / e).round()),
+            e => format!("{} s", e),
+        }),
+        camera.iso.map(|iso| format!("ISO {}", iso)),
+    ];
+    lines
+        .into_iter()
+        .flatten()
+        .fold(column().spacing(5), |col, line| col.push(text(line)))
+        .push(text(&details.hash).size(12))
+        .into()
+}
@@ -1,2 +1,7 @@
+pub mod duplicates;
 pub mod gallery;
+pub mod info;
+pub mod scroll;
 pub mod tags;
+pub mod timeline;
+pub mod viewer;
@@ -0,0 +1,191 @@
+use std::cell::Cell;
+
+use iced_native::event::{self, Event};
+use iced_native::layout::{self, Layout};
+use iced_native::mouse;
+use iced_native::overlay;
+use iced_native::renderer;
+use iced_native::widget::scrollable;
+use iced_native::{Clipboard, Length, Point, Rectangle, Shell, Size};
+use iced_pure::widget::tree::{self, Tree};
+use iced_pure::widget::Scrollable;
+use iced_pure::{Element, Widget};
+
+/// A [`Scrollable`] which the application can scroll to a position, e.g. to show a date picked
+/// in the timeline. Scrollables in iced can otherwise only be scrolled by the user.
+pub struct Scroll<'a, Message, Renderer> {
+    inner: Scrollable<'a, Message, Renderer>,
+    jump: Option<Jump<'a>>,
+}
+
+/// Request to scroll to the y coordinate computed from the size of the content. Requests are
+/// numbered, and each is applied only once - so that the user can scroll away afterwards.
+struct Jump<'a> {
+    id: u64,
+    y: Box<dyn Fn(Size) -> f32 + 'a>,
+}
+
+struct State {
+    /// Id of the last applied jump.
+    jumped: u64,
+    /// Bounds of the scrollable and of its content, as last drawn.
+    bounds: Cell<Option<(Rectangle, Rectangle)>>,
+}
+
+impl<'a, Message, Renderer> Scroll<'a, Message, Renderer>
+where
+    Renderer: iced_native::Renderer,
+{
+    pub fn new(content: impl Into<Element<'a, Message, Renderer>>) -> Self {
+        Self {
+            inner: Scrollable::new(content),
+            jump: None,
+        }
+    }
+
+    /// Scroll so that the y coordinate returned by `y` (given the size of the content) is at the
+    /// top, unless the jump with this `id` (or a later one) was already done.
+    pub fn jump(mut self, id: u64, y: impl Fn(Size) -> f32 + 'a) -> Self {
+        self.jump = Some(Jump { id, y: Box::new(y) });
+        self
+    }
+
+    fn inner(&self) -> &dyn Widget<Message, Renderer> {
+        &self.inner
+    }
+}
+
+impl<'a, Message, Renderer> Widget<Message, Renderer> for Scroll<'a, Message, Renderer>
+where
+    Renderer: iced_native::Renderer,
+{
+    fn width(&self) -> Length {
+        self.inner().width()
+    }
+
+    fn height(&self) -> Length {
+        self.inner().height()
+    }
+
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<State>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(State {
+            jumped: 0,
+            bounds: Cell::new(None),
+        })
+    }
+
+    fn children(&self) -> Vec<Tree> {
+        vec![Tree::new(self.inner())]
+    }
+
+    fn diff(&self, tree: &mut Tree) {
+        tree.children[0].diff(self.inner());
+        let jump = match &self.jump {
+            Some(jump) => jump,
+            None => return,
+        };
+        let state = tree.state.downcast_mut::<State>();
+        let (bounds, content) = match state.bounds.get() {
+            Some(bounds) if jump.id > state.jumped => bounds,
+            _ => return,
+        };
+        state.jumped = jump.id;
+        let range = content.height - bounds.height;
+        if range > 0. {
+            let y = (jump.y)(content.size());
+            tree.children[0]
+                .state
+                .downcast_mut::<scrollable::State>()
+                .scroll_to(y / range, bounds, content);
+        }
+    }
+
+    fn layout(&self, renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        self.inner().layout(renderer, limits)
+    }
+
+    fn draw(
+        &self,
+        tree: &Tree,
+        renderer: &mut Renderer,
+        style: &renderer::Style,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        viewport: &Rectangle,
+    ) {
+        if let Some(content) = layout.children().next() {
+            let state = tree.state.downcast_ref::<State>();
+            state.bounds.set(Some((layout.bounds(), content.bounds())));
+        }
+        self.inner().draw(
+            &tree.children[0],
+            renderer,
+            style,
+            layout,
+            cursor_position,
+            viewport,
+        )
+    }
+
+    fn on_event(
+        &mut self,
+        tree: &mut Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        renderer: &Renderer,
+        clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+    ) -> event::Status {
+        self.inner.on_event(
+            &mut tree.children[0],
+            event,
+            layout,
+            cursor_position,
+            renderer,
+            clipboard,
+            shell,
+        )
+    }
+
+    fn mouse_interaction(
+        &self,
+        tree: &Tree,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        viewport: &Rectangle,
+        renderer: &Renderer,
+    ) -> mouse::Interaction {
+        self.inner().mouse_interaction(
+            &tree.children[0],
+            layout,
+            cursor_position,
+            viewport,
+            renderer,
+        )
+    }
+
+    fn overlay<'b>(
+        &'b self,
+        tree: &'b mut Tree,
+        layout: Layout<'_>,
+        renderer: &Renderer,
+    ) -> Option<overlay::Element<'b, Message, Renderer>> {
+        self.inner()
+            .overlay(&mut tree.children[0], layout, renderer)
+    }
+}
+
+impl<'a, Message, Renderer> From<Scroll<'a, Message, Renderer>> for Element<'a, Message, Renderer>
+where
+    Message: 'a,
+    Renderer: 'a + iced_native::Renderer,
+{
+    fn from(scroll: Scroll<'a, Message, Renderer>) -> Self {
+        Element::new(scroll)
+    }
+}
@@ -1,40 +1,110 @@
-use iced::pure::{button, column, row, text, Element};
+use iced::pure::{button, checkbox, column, row, text, text_input, Element};
 
 pub struct Panel {
     tags: Vec<tag::Tag>,
+    /// Name of a new tag being typed in by the user.
+    new_name: String,
+    show_hidden: bool,
 }
 
 #[derive(Debug, Clone)]
 pub enum Event {
     OfNthTag(usize, tag::Event),
+    EditNewName(String),
+    Create(String),
+    SetShowHidden(bool),
 }
 
 impl Panel {
     pub fn new(tags: &[tag::Tag]) -> Self {
-        Self { tags: tags.into() }
+        Self {
+            tags: tags.into(),
+            new_name: String::new(),
+            show_hidden: false,
+        }
+    }
+
+    pub fn with_show_hidden(mut self, show_hidden: bool) -> Self {
+        self.show_hidden = show_hidden;
+        self
+    }
+
+    pub fn get(&self, i: usize) -> Option<&tag::Tag> {
+        self.tags.get(i)
+    }
+
+    /// Whether `name` (trimmed) can be given to the tag at index `own`, or to a new tag if `None`:
+    /// it must not be empty, nor taken by another tag.
+    pub fn name_available(&self, name: &str, own: Option<usize>) -> bool {
+        let name = name.trim();
+        !name.is_empty()
+            && !self
+                .tags
+                .iter()
+                .enumerate()
+                .any(|(i, tag)| tag.name == name && Some(i) != own)
     }
 
     pub fn update(&mut self, event: Event) {
         match event {
+            Event::OfNthTag(i, tag::Event::Delete) => {
+                if i < self.tags.len() {
+                    self.tags.remove(i);
+                }
+            }
+            Event::OfNthTag(i, tag::Event::Rename(name))
+                if !self.name_available(&name, Some(i)) => {}
             Event::OfNthTag(i, tag_event) => {
                 if let Some(tag) = self.tags.get_mut(i) {
                     tag.update(tag_event)
                 }
             }
+            Event::EditNewName(name) => {
+                self.new_name = name;
+            }
+            Event::Create(name) => {
+                if self.name_available(&name, None) {
+                    self.new_name.clear();
+                    self.tags.push(tag::Tag::new(
+                        name.trim().to_string(),
+                        Some(false),
+                        false,
+                        0,
+                    ));
+                }
+            }
+            Event::SetShowHidden(show_hidden) => {
+                self.show_hidden = show_hidden;
+            }
         }
     }
 
-    pub fn view(&self) -> Element<Event> {
+    pub fn view(&self) -> Element<'_, Event> {
         // TODO: wrap in Scrollable
-        let tags: Element<_> = self
+        let tags = self
             .tags
             .iter()
             .enumerate()
             .fold(column().spacing(20), |col, (i, tag)| {
-                col.push(tag.view().map(move |msg| Event::OfNthTag(i, msg)))
-            })
-            .into();
-        tags
+                let renamable = tag
+                    .editing()
+                    .map_or(false, |name| self.name_available(name, Some(i)));
+                col.push(tag.view(renamable).map(move |msg| Event::OfNthTag(i, msg)))
+            });
+        let mut input = text_input("New tag...", &self.new_name, Event::EditNewName).padding(10);
+        let mut add = button(text("Add")).padding(10);
+        if self.name_available(&self.new_name, None) {
+            input = input.on_submit(Event::Create(self.new_name.clone()));
+            add = add.on_press(Event::Create(self.new_name.clone()));
+        }
+        let new_tag = row().spacing(20).push(input).push(add);
+        tags.push(new_tag)
+            .push(checkbox(
+                "Show hidden",
+                self.show_hidden,
+                Event::SetShowHidden,
+            ))
+            .into()
     }
 }
 
@@ -45,6 +115,8 @@ impl FromIterator<tag::Tag> for Panel {
     {
         Self {
             tags: iter.into_iter().collect(),
+            new_name: String::new(),
+            show_hidden: false,
         }
     }
 }
@@ -60,6 +132,14 @@ pub mod tag {
     pub enum Event {
         SetSelected(bool),
         SetHidden(bool),
+        SetFilter(Option<bool>),
+        StartRename,
+        EditName(String),
+        Rename(String),
+        CancelRename,
+        AskDelete,
+        Delete,
+        CancelDelete,
     }
 
     #[derive(Clone)]
@@ -68,14 +148,28 @@ pub mod tag {
         // TODO: are there three-state checkboxes in iced?
         pub selected: Option<bool>,
         pub hidden: bool,
+        /// Number of all files carrying the tag (not only the selected ones).
+        pub files: u32,
+        /// Whether gallery shows only files with this tag (`Some(true)`), only files without this
+        /// tag (`Some(false)`), or doesn't care (`None`).
+        pub filter: Option<bool>,
+
+        /// New name being typed in by the user, if renaming is in progress.
+        editing: Option<String>,
+        /// Whether deletion was requested and awaits confirmation.
+        deleting: bool,
     }
 
     impl Tag {
-        pub fn new(name: String, selected: Option<bool>, hidden: bool) -> Self {
+        pub fn new(name: String, selected: Option<bool>, hidden: bool, files: u32) -> Self {
             Self {
                 name,
                 selected,
                 hidden,
+                files,
+                filter: None,
+                editing: None,
+                deleting: false,
             }
         }
 
@@ -87,12 +181,57 @@ pub mod tag {
                 Event::SetHidden(hidden) => {
                     self.hidden = hidden;
                 }
+                Event::SetFilter(filter) => {
+                    self.filter = filter;
+                }
+                Event::StartRename => {
+                    self.editing = Some(self.name.clone());
+                }
+                Event::EditName(name) => {
+                    self.editing = Some(name);
+                }
+                Event::Rename(name) => {
+                    self.name = name.trim().to_string();
+                    self.editing = None;
+                }
+                Event::CancelRename => {
+                    self.editing = None;
+                }
+                Event::AskDelete => {
+                    self.deleting = true;
+                }
+                // Note: removing the tag from list is handled by the parent Panel.
+                Event::Delete | Event::CancelDelete => {
+                    self.deleting = false;
+                }
             }
         }
 
-        pub fn view(&self) -> Element<Event> {
-            // TODO[LATER]: handle `name` editing
+        /// New name being typed in, if renaming is in progress.
+        pub fn editing(&self) -> Option<&str> {
+            self.editing.as_deref()
+        }
+
+        /// View of the tag; `renamable` tells if the new name being typed in can be accepted.
+        pub fn view(&self, renamable: bool) -> Element<'_, Event> {
             // TODO[LATER]: make buttons align vertically among others
+            if self.deleting {
+                return row()
+                    .spacing(20)
+                    .align_items(Alignment::Center)
+                    .push(text(format!(
+                        "Delete tag '{}' from {} file(s)?",
+                        &self.name, self.files
+                    )))
+                    .push(button(text("Delete")).on_press(Event::Delete).padding(10))
+                    .push(
+                        button(text("Cancel"))
+                            .on_press(Event::CancelDelete)
+                            .padding(10),
+                    )
+                    .into();
+            }
+
             let selected_icon = match self.selected {
                 None => res::icon_minus_squared_alt(),
                 Some(true) => res::icon_ok_squared(),
@@ -102,15 +241,41 @@ pub mod tag {
                 false => res::icon_eye(),
                 true => res::icon_eye_off(),
             };
+            let (filter_label, next_filter) = match self.filter {
+                None => ("any", Some(true)),
+                Some(true) => ("with", Some(false)),
+                Some(false) => ("without", None),
+            };
+            let selected = button(selected_icon)
+                .on_press(Event::SetSelected(!self.selected.unwrap_or(false)))
+                // TODO: .style(style::Button::Icon) - see: iced/examples/todos/
+                .padding(10);
+
+            if let Some(editing) = &self.editing {
+                let mut input = text_input("Tag name", editing, Event::EditName).padding(10);
+                let mut ok = button(text("OK")).padding(10);
+                if renamable {
+                    input = input.on_submit(Event::Rename(editing.clone()));
+                    ok = ok.on_press(Event::Rename(editing.clone()));
+                }
+                return row()
+                    .spacing(20)
+                    .align_items(Alignment::Center)
+                    .push(selected)
+                    .push(input)
+                    .push(ok)
+                    .push(
+                        button(text("Cancel"))
+                            .on_press(Event::CancelRename)
+                            .padding(10),
+                    )
+                    .into();
+            }
+
             row()
                 .spacing(20)
                 .align_items(Alignment::Center)
-                .push(
-                    button(selected_icon)
-                        .on_press(Event::SetSelected(!self.selected.unwrap_or(false)))
-                        // TODO: .style(style::Button::Icon) - see: iced/examples/todos/
-                        .padding(10),
-                )
+                .push(selected)
                 .push(text(&self.name)) //.width(Length::Fill))
                 .push(
                     button(hidden_icon)
@@ -118,6 +283,21 @@ pub mod tag {
                         // TODO: .style(style::Button::Icon) - see: iced/examples/todos/
                         .padding(10),
                 )
+                .push(
+                    button(text(filter_label))
+                        .on_press(Event::SetFilter(next_filter))
+                        .padding(10),
+                )
+                .push(
+                    button(text("Rename"))
+                        .on_press(Event::StartRename)
+                        .padding(10),
+                )
+                .push(
+                    button(text("Delete"))
+                        .on_press(Event::AskDelete)
+                        .padding(10),
+                )
                 .into()
         }
     }
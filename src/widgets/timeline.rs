@@ -0,0 +1,74 @@
+use chrono::NaiveDate;
+use iced::pure::{button, column, scrollable, text, Element};
+use iced::Length;
+use itertools::Itertools;
+
+use crate::db::Month;
+
+/// Years and months of files shown in the gallery, with counts of files. Months are listed only
+/// for the expanded year.
+pub struct Timeline {
+    months: Vec<Month>,
+    expanded: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Scroll the gallery to the file at `offset`, and expand the `year`.
+    Jump { year: i32, offset: u32 },
+}
+
+impl Timeline {
+    pub fn new(months: Vec<Month>) -> Self {
+        Self {
+            months,
+            expanded: None,
+        }
+    }
+
+    pub fn with_expanded(mut self, year: Option<i32>) -> Self {
+        self.expanded = year;
+        self
+    }
+
+    pub fn expanded(&self) -> Option<i32> {
+        self.expanded
+    }
+
+    pub fn update(&mut self, event: Event) {
+        let Event::Jump { year, .. } = event;
+        self.expanded = Some(year);
+    }
+
+    pub fn view(&self) -> Element<'_, Event> {
+        let mut col = column().spacing(2);
+        for (year, months) in &self.months.iter().group_by(|m| m.year) {
+            let months: Vec<_> = months.collect();
+            let files: u32 = months.iter().map(|m| m.files).sum();
+            let offset = months[0].offset;
+            col = col.push(
+                button(text(format!("{} ({})", year, files)))
+                    .on_press(Event::Jump { year, offset })
+                    .width(Length::Fill)
+                    .padding(5),
+            );
+            if self.expanded != Some(year) {
+                continue;
+            }
+            for m in months {
+                let name = NaiveDate::from_ymd_opt(m.year, m.month, 1)
+                    .map_or(m.month.to_string(), |d| d.format("%b").to_string());
+                col = col.push(
+                    button(text(format!("  {} ({})", name, m.files)).size(16))
+                        .on_press(Event::Jump {
+                            year,
+                            offset: m.offset,
+                        })
+                        .width(Length::Fill)
+                        .padding(3),
+                );
+            }
+        }
+        scrollable(col).height(Length::Fill).into()
+    }
+}
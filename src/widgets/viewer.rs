@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+use iced::pure::{button, column, image, row, text, Element};
+use iced::Length;
+use iced_native::image::Handle;
+
+/// Full-size view of a single file from the gallery.
+pub struct Viewer {
+    /// Index of the viewed file in gallery order.
+    pub offset: u32,
+    /// Path to the original file, if it was found on any connected disk.
+    original: Option<PathBuf>,
+    /// Decoded image of the original file, or the error decoding it. None while it's decoded in
+    /// background.
+    image: Option<Result<Handle, String>>,
+    has_next: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    Previous,
+    Next,
+    Close,
+}
+
+impl Viewer {
+    pub fn new(offset: u32, original: Option<PathBuf>, has_next: bool) -> Self {
+        Self {
+            offset,
+            original,
+            image: None,
+            has_next,
+        }
+    }
+
+    /// Show the image decoded in background.
+    pub fn set_image(&mut self, image: Result<Handle, String>) {
+        self.image = Some(image);
+    }
+
+    pub fn has_next(&self) -> bool {
+        self.has_next
+    }
+
+    pub fn view(&self) -> Element<'_, Event> {
+        let previous = button(text("Previous")).padding(10);
+        let previous = match self.offset {
+            0 => previous,
+            _ => previous.on_press(Event::Previous),
+        };
+        let next = button(text("Next")).padding(10);
+        let next = match self.has_next {
+            false => next,
+            true => next.on_press(Event::Next),
+        };
+        let path = match &self.original {
+            Some(path) => path.display().to_string(),
+            None => "File not found on any connected disk".to_string(),
+        };
+        let nav = row()
+            .spacing(20)
+            .push(button(text("Back")).on_press(Event::Close).padding(10))
+            .push(previous)
+            .push(next)
+            .push(text(path));
+
+        let content: Element<_> = match (&self.original, &self.image) {
+            (None, _) => column().into(),
+            (Some(_), None) => text("Loading...").into(),
+            (Some(_), Some(Err(err))) => text(err).into(),
+            (Some(_), Some(Ok(handle))) => image(handle.clone())
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .into(),
+        };
+        column().spacing(20).push(nav).push(content).into()
+    }
+}